@@ -0,0 +1,27 @@
+"""
+A stand-in for the onpy package that never talks to Onshape. Scripts run
+against it record every sketch and feature they create into a JSON trace,
+written to the path in ONPY_TRACE_FILE when the script exits.
+"""
+
+from onpy.model import Document, PartStudio, Sketch, Part
+from onpy.util.exceptions import (
+    OnPyException,
+    OnPyParameterError,
+    OnPyFeatureError,
+)
+from onpy import trace
+import onpy.api.rest_api
+
+
+def get_document(document_id: str | None = None, name: str | None = None) -> Document:
+    if document_id is None and name is None:
+        raise OnPyParameterError("Either a document id or a name must be provided")
+    return Document(document_id or name)
+
+
+def create_document(name: str, description: str | None = None) -> Document:
+    return Document(name)
+
+
+trace.install()
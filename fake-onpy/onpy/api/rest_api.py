@@ -0,0 +1,7 @@
+"""
+Kept so scripts that point onpy at another Onshape API still import
+"""
+
+
+class RestApi:
+    BASE_URL = "https://cad.onshape.com/api/v6"
@@ -0,0 +1,386 @@
+"""
+Documents, part studios, sketches, and features, as far as Polybrain uses them
+"""
+
+import math
+
+from onpy import trace
+from onpy.util.exceptions import OnPyFeatureError, OnPyParameterError
+
+
+def _point(value, name: str) -> list[float]:
+    try:
+        x, y = value
+        return [float(x), float(y)]
+    except (TypeError, ValueError):
+        raise OnPyParameterError(f"{name} must be an (x, y) pair, got {value!r}")
+
+
+def _number(value, name: str) -> float:
+    if isinstance(value, bool) or not isinstance(value, (int, float)):
+        raise OnPyParameterError(f"{name} must be a number, got {value!r}")
+    return float(value)
+
+
+class Plane:
+    """One of the default planes, or a plane offset from one"""
+
+    def __init__(self, name: str, base: str, offset: float = 0.0):
+        self.name = name
+        self.base = base
+        self.offset = offset
+
+    def __repr__(self) -> str:
+        return f"Plane({self.name!r})"
+
+
+class Document:
+    def __init__(self, document_id: str):
+        self.id = document_id
+        self._partstudio = PartStudio(self)
+
+    def get_partstudio(
+        self,
+        element_id: str | None = None,
+        name: str | None = None,
+        wid: str | None = None,
+    ) -> "PartStudio":
+        return self._partstudio
+
+    @property
+    def partstudio(self) -> "PartStudio":
+        return self._partstudio
+
+
+class FeatureList:
+    def __init__(self):
+        self.top_plane = Plane("Top", "Top")
+        self.front_plane = Plane("Front", "Front")
+        self.right_plane = Plane("Right", "Right")
+        self._features: list = []
+
+    def add(self, feature) -> None:
+        self._features.append(feature)
+
+    def clear(self) -> None:
+        self._features.clear()
+
+    def __iter__(self):
+        return iter(self._features)
+
+    def __len__(self) -> int:
+        return len(self._features)
+
+    def __getitem__(self, name: str):
+        for feature in self._features:
+            if feature.name == name:
+                return feature
+        raise KeyError(f"No feature is named {name!r}")
+
+
+class Part:
+    def __init__(self, name: str, feature: str):
+        self.name = name
+        # The feature that created the part
+        self.feature = feature
+
+    @property
+    def faces(self) -> "PartFaces":
+        return PartFaces(self)
+
+    def __repr__(self) -> str:
+        return f"Part({self.name!r})"
+
+
+class PartList:
+    def __init__(self):
+        self._parts: list[Part] = []
+
+    def __iter__(self):
+        return iter(self._parts)
+
+    def __len__(self) -> int:
+        return len(self._parts)
+
+    def __getitem__(self, index: int) -> Part:
+        return self._parts[index]
+
+    def get(self, name: str) -> Part:
+        for part in self._parts:
+            if part.name == name:
+                return part
+        raise OnPyParameterError(f"No part is named {name!r}")
+
+
+class PartFaces:
+    """Faces of a built part. The fake backend can't locate them, so they can
+    be queried but not sketched on or extruded."""
+
+    def __init__(self, part: Part):
+        self.part = part
+
+    def contains_point(self, point) -> "PartFaces":
+        return self
+
+    def closest_to(self, point) -> "PartFaces":
+        return self
+
+    def largest(self) -> "PartFaces":
+        return self
+
+    def smallest(self) -> "PartFaces":
+        return self
+
+
+class SketchFaces:
+    """The regions of a sketch, as passed to extrudes and lofts"""
+
+    def __init__(self, sketch: "Sketch"):
+        self.sketch = sketch
+
+    def contains_point(self, point) -> "SketchFaces":
+        _point(point, "point")
+        return self
+
+    def closest_to(self, point) -> "SketchFaces":
+        return self
+
+    def largest(self) -> "SketchFaces":
+        return self
+
+    def smallest(self) -> "SketchFaces":
+        return self
+
+    def __len__(self) -> int:
+        return 1 if self.sketch._entities else 0
+
+
+class SketchLine:
+    def __init__(self, index: int, start: list[float], end: list[float]):
+        self.index = index
+        self.start = start
+        self.end = end
+
+
+class SketchCircle:
+    def __init__(self, index: int, center: list[float], radius: float):
+        self.index = index
+        self.center = center
+        self.radius = radius
+
+
+class SketchArc:
+    def __init__(self, index: int, center: list[float], radius: float):
+        self.index = index
+        self.center = center
+        self.radius = radius
+
+
+class Sketch:
+    def __init__(self, name: str, plane: Plane):
+        self.name = name
+        self.plane = plane
+        # What the trace calls the sketch, set once it's recorded
+        self.id = name
+        self._entities: list = []
+        self._record = {
+            "name": name,
+            "plane": {"base": plane.base, "offset": plane.offset},
+            "entities": [],
+        }
+
+    def _add(self, entity, record: dict):
+        self._entities.append(entity)
+        self._record["entities"].append(record)
+        return entity
+
+    def add_line(self, start, end) -> SketchLine:
+        start, end = _point(start, "start"), _point(end, "end")
+        if start == end:
+            raise OnPyParameterError("A line's start and end points must be different")
+        return self._add(
+            SketchLine(len(self._entities), start, end),
+            {"type": "line", "start": start, "end": end},
+        )
+
+    def add_circle(self, center, radius) -> SketchCircle:
+        center, radius = _point(center, "center"), _number(radius, "radius")
+        if radius <= 0:
+            raise OnPyParameterError("A circle's radius must be positive")
+        return self._add(
+            SketchCircle(len(self._entities), center, radius),
+            {"type": "circle", "center": center, "radius": radius},
+        )
+
+    def add_centerpoint_arc(self, centerpoint, radius, start_angle, end_angle) -> SketchArc:
+        center, radius = _point(centerpoint, "centerpoint"), _number(radius, "radius")
+        start_angle = _number(start_angle, "start_angle")
+        end_angle = _number(end_angle, "end_angle")
+        if radius <= 0:
+            raise OnPyParameterError("An arc's radius must be positive")
+        if math.isclose(start_angle % 360, end_angle % 360):
+            raise OnPyParameterError("An arc's start and end angles must be different")
+        return self._add(
+            SketchArc(len(self._entities), center, radius),
+            {
+                "type": "centerpoint_arc",
+                "center": center,
+                "radius": radius,
+                "start_angle": start_angle,
+                "end_angle": end_angle,
+            },
+        )
+
+    def add_fillet(self, line_1, line_2, radius) -> SketchArc:
+        radius = _number(radius, "radius")
+        for line in (line_1, line_2):
+            if not isinstance(line, SketchLine) or line not in self._entities:
+                raise OnPyParameterError("Fillets can only join two lines in the same sketch")
+        if line_1 is line_2:
+            raise OnPyParameterError("A fillet needs two different lines")
+        if radius <= 0:
+            raise OnPyParameterError("A fillet's radius must be positive")
+        shared = [p for p in (line_1.start, line_1.end) if p in (line_2.start, line_2.end)]
+        if not shared:
+            raise OnPyFeatureError("Fillet lines must meet at a common endpoint")
+        return self._add(
+            SketchArc(len(self._entities), shared[0], radius),
+            {"type": "fillet", "lines": [line_1.index, line_2.index], "radius": radius},
+        )
+
+    def trace_points(self, *points, end_connect: bool = True) -> list[SketchLine]:
+        if len(points) < 2:
+            raise OnPyParameterError("At least two points are needed to trace lines")
+        pairs = list(zip(points, points[1:]))
+        if end_connect:
+            pairs.append((points[-1], points[0]))
+        return [self.add_line(start, end) for start, end in pairs]
+
+    def add_corner_rectangle(self, corner_1, corner_2) -> list[SketchLine]:
+        (x1, y1), (x2, y2) = _point(corner_1, "corner_1"), _point(corner_2, "corner_2")
+        if x1 == x2 or y1 == y2:
+            raise OnPyParameterError("A rectangle's corners must differ along both axes")
+        return self.trace_points((x1, y1), (x2, y1), (x2, y2), (x1, y2))
+
+    @property
+    def faces(self) -> SketchFaces:
+        return SketchFaces(self)
+
+
+class Feature:
+    def __init__(self, name: str, created_parts: list[Part] | None = None):
+        self.name = name
+        self._created_parts = created_parts or []
+
+    def get_created_parts(self) -> list[Part]:
+        return list(self._created_parts)
+
+
+class PartStudio:
+    def __init__(self, document: Document):
+        self.document = document
+        self.features = FeatureList()
+        self.parts = PartList()
+
+    def wipe(self) -> None:
+        self.features.clear()
+        self.parts = PartList()
+        trace.record_wipe()
+
+    def _new_part(self, feature: str) -> Part:
+        part = Part(f"Part {len(self.parts) + 1}", feature)
+        self.parts._parts.append(part)
+        trace.record_part()
+        return part
+
+    def _sketch_of(self, faces, name: str) -> Sketch:
+        if isinstance(faces, Sketch):
+            sketch = faces
+        elif isinstance(faces, SketchFaces):
+            sketch = faces.sketch
+        elif isinstance(faces, PartFaces):
+            raise OnPyFeatureError(
+                f"{name}: the fake onpy backend can only use sketch regions, not part faces"
+            )
+        else:
+            raise OnPyParameterError(f"{name}: expected a sketch or sketch faces, got {faces!r}")
+        if not sketch._entities:
+            raise OnPyFeatureError(f"{name}: sketch {sketch.name!r} has no regions to use")
+        return sketch
+
+    def add_sketch(self, plane, name: str = "New Sketch") -> Sketch:
+        if isinstance(plane, PartFaces):
+            raise OnPyFeatureError(
+                "The fake onpy backend can only sketch on default and offset planes"
+            )
+        if not isinstance(plane, Plane):
+            raise OnPyParameterError(f"Sketches must be placed on a plane, got {plane!r}")
+        sketch = Sketch(name, plane)
+        self.features.add(sketch)
+        sketch.id = trace.record_sketch(sketch._record)
+        return sketch
+
+    def add_offset_plane(self, target, distance, name: str = "Offset Plane") -> Plane:
+        if not isinstance(target, Plane):
+            raise OnPyParameterError(f"Offset planes must be offset from a plane, got {target!r}")
+        plane = Plane(name, target.base, target.offset + _number(distance, "distance"))
+        self.features.add(plane)
+        return plane
+
+    def add_extrude(
+        self,
+        faces,
+        distance,
+        name: str = "New Extrude",
+        merge_with: Part | None = None,
+        subtract_from: Part | None = None,
+    ) -> Feature:
+        sketch = self._sketch_of(faces, name)
+        distance = _number(distance, "distance")
+        if distance == 0:
+            raise OnPyParameterError(f"{name}: extrude distance can't be zero")
+        if merge_with is not None and subtract_from is not None:
+            raise OnPyParameterError(f"{name}: can't both merge with and subtract from a part")
+
+        target = merge_with if merge_with is not None else subtract_from
+        if target is not None and target not in list(self.parts):
+            raise OnPyParameterError(f"{name}: {target!r} is not a part in this part studio")
+
+        if subtract_from is not None:
+            operation, created = "subtract", []
+        elif merge_with is not None:
+            operation, created = "add", []
+        else:
+            operation, created = "new", [self._new_part(name)]
+
+        feature = Feature(name, created)
+        self.features.add(feature)
+        trace.record_feature(
+            {
+                "type": "extrude",
+                "name": name,
+                "sketch": sketch.id,
+                "distance": distance,
+                "operation": operation,
+                "target": target.feature if target is not None else None,
+            }
+        )
+        return feature
+
+    def add_loft(self, start, end, name: str = "Loft") -> Feature:
+        start_sketch = self._sketch_of(start, name)
+        end_sketch = self._sketch_of(end, name)
+        if start_sketch is end_sketch:
+            raise OnPyFeatureError(f"{name}: a loft needs two different sketches")
+
+        feature = Feature(name, [self._new_part(name)])
+        self.features.add(feature)
+        trace.record_feature(
+            {
+                "type": "loft",
+                "name": name,
+                "start_sketch": start_sketch.id,
+                "end_sketch": end_sketch.id,
+            }
+        )
+        return feature
@@ -0,0 +1,52 @@
+"""
+Records what a script builds, in the shape of a Polybrain model plan
+"""
+
+import atexit
+import json
+import os
+
+_trace = {
+    "wiped": False,
+    "model": {
+        "summary": "Traced from an OnPy script",
+        "part_count": 0,
+        "sketches": [],
+        "features": [],
+    },
+}
+
+
+def record_wipe() -> None:
+    _trace["wiped"] = True
+    _trace["model"]["part_count"] = 0
+    _trace["model"]["sketches"].clear()
+    _trace["model"]["features"].clear()
+
+
+def record_sketch(sketch: dict) -> str:
+    """Records a sketch and returns the id features reference it by. Scripts
+    can give sketches the same name, so the id starts with the sketch's index
+    in the trace."""
+    sketches = _trace["model"]["sketches"]
+    sketch["name"] = f"#{len(sketches)} {sketch['name']}"
+    sketches.append(sketch)
+    return sketch["name"]
+
+
+def record_part() -> None:
+    _trace["model"]["part_count"] += 1
+
+
+def record_feature(feature: dict) -> None:
+    _trace["model"]["features"].append(feature)
+
+
+def _write() -> None:
+    path = os.environ.get("ONPY_TRACE_FILE", "onpy_trace.json")
+    with open(path, "w") as f:
+        json.dump(_trace, f, indent=2)
+
+
+def install() -> None:
+    atexit.register(_write)
@@ -0,0 +1,15 @@
+"""
+The errors onpy raises, with the same names as the real package
+"""
+
+
+class OnPyException(Exception):
+    pass
+
+
+class OnPyParameterError(OnPyException):
+    """A method was called with an invalid argument"""
+
+
+class OnPyFeatureError(OnPyException):
+    """Onshape would fail to regenerate the feature"""
@@ -1,14 +1,27 @@
 from enum import Enum
+from typing import Optional, Union
 from pydantic import BaseModel
 
 
 class SessionStartRequest(BaseModel):
     user_token: str
     onshape_document_id: str
+    pipeline: Optional[list[str]] = None
+    resume_session_id: Optional[str] = None
+    display_unit: Optional[str] = None  # one of mm, cm, m, in, ft
+    pessimist_max_turns: Optional[int] = None
+    approve_outline: bool = False
+    allow_wipe: bool = False  # replace the first part studio instead of adding one
+    edit_existing: bool = False  # change the model already in the part studio
+    workspace_id: Optional[str] = None
+    element_id: Optional[str] = None
+    part_studio_name: Optional[str] = None  # used when element_id is not set
+    export_formats: Optional[list[str]] = None  # STEP, STL, PARASOLID; none by default
 
 
 class SessionStartResponse(BaseModel):
     session_id: str
+    resumed: bool = False
 
 
 class UserPromptInitial(BaseModel):
@@ -17,11 +30,15 @@ class UserPromptInitial(BaseModel):
 
 class UserInputResponse(BaseModel):
     response: str
+    # one of approve, request_changes, edit, undo, stop, or
+    # {"revert_to": {"iteration": n}}
+    action: Optional[Union[str, dict]] = None
 
 
 class ServerResponse(BaseModel):
     response_type: str
     content: str
+    payload: Optional[dict] = None
 
 
 class ApiCredentials(BaseModel):
@@ -1,15 +1,20 @@
-use futures::Future;
 use llm_chain::prompt;
 use llm_chain::tools::ToolUseError;
 use llm_chain::{executor, options, parameters, tools::ToolCollection};
 use llm_chain_openai::chatgpt::Model;
-use std::{error::Error, pin::Pin};
+use std::error::Error;
 
-use crate::chain::tools::misc::deserialize_output;
+use crate::chain::capabilities::{self, CapabilityRegistry};
+use crate::chain::plan::{ModelPlan, PLAN_FORMAT};
+use crate::chain::plan_lint::validate_plan;
+use crate::chain::session_io::SessionIo;
+use crate::chain::tools::misc::{deserialize_invocation, deserialize_output};
 use crate::chain::tools::report_tool::{Report, ReportError, ReportInput, ReportOutput};
 use crate::chain::tools::user_input_tool::{
     UserQuery, UserQueryError, UserQueryInput, UserQueryOutput,
 };
+use crate::chain::units::{normalize_dimensions, LengthUnit};
+use crate::chain::util::trim_assistant_prefix;
 
 use async_trait::async_trait;
 use llm_chain::{
@@ -50,31 +55,25 @@ A coworker has provided the following mathematical notes:
 ```txt
 {{math_notes}}
 ```
-
+{{existing_model}}{{revision_notes}}
 OnPy is a limited tool, so your instructions MUST conform to the following 
 constraints:
 
-Sketches can only be created on 2D flat planes. Within these sketches,
-users can ONLY draw:
-- Straight lines between two points
-- Circles at a specified origin
-- Fillets between two lines
-- Centerpoint arcs
-
-Users can copy, mirror, and pattern their designs.
-
-After creating a sketch, the following features are available. If
-a feature is not listed here, then it cannot be used in OnPy:
-- Extrusions
-- Offset Planes
-- Lofts
-
+{{capabilities}}
 Final Considerations:
 - There are no sketch constraints in OnPy; do not mention them.
 - OnPy cannot control color, or surface finish.
 - Your report is going to another employee, so now is the chance to ask any
 questions to the user about desired measurements.
 - All OnPy units are in Inches. Only include units of Inches in your response
+- Measurements the user gives in other units are followed by their value in
+Inches, in parentheses. Always use the value in Inches.
+
+Your report is a model plan, submitted through the Report tool. It MUST follow
+this PLAN FORMAT:
+```yaml
+{{plan_format}}
+```
 
 ================
 
@@ -83,6 +82,8 @@ questions to the user about desired measurements.
 You are encouraged to explain your thoughts as much as possible. Prefix
 all thoughts with a YAML comment (i.e., a line that begins with #)
 
+{{final_notice}}
+
 ===== PREVIOUS COMMANDS & THOUGHTS =====
 
 ```yaml
@@ -94,12 +95,76 @@ all thoughts with a YAML comment (i.e., a line that begins with #)
 ```yaml
 ";
 
+const FINAL_ITERATION_NOTICE: &str = "\
+IMPORTANT: You are out of time. In this response you MUST invoke the Report
+tool with your complete report. Do not ask the user any more questions; make
+reasonable assumptions for anything that is still unknown.
+";
+
+const FALLBACK_REPORT_PROMPT: &str = "\
+You are a professional mechanical engineer. A colleague was planning how to
+create the following model in a CAD program called OnPy, but they never
+submitted their final report.
+
+The description of the model to create is:
+```txt
+{{model_description}}
+```
+
+A coworker has provided the following mathematical notes:
+```txt
+{{math_notes}}
+```
+{{existing_model}}{{revision_notes}}
+The plan MUST conform to OnPy's constraints:
+
+{{capabilities}}
+Your colleague's notes, questions, and the user's answers were:
+```yaml
+{{scratchpad}}
+```
+
+Using only the information above, write the final report as a model plan in
+the following PLAN FORMAT:
+```yaml
+{{plan_format}}
+```
+
+Respond with the YAML plan only.
+";
+
+const REVISION_NOTES: &str = "
+The user reviewed an earlier plan and asked for changes. Your new plan MUST
+make these changes and keep everything else the same.
+
+The earlier plan was:
+```txt
+{{previous_outline}}
+```
+
+The user's requested changes are:
+```txt
+{{feedback}}
+```
+";
+
 const MAX_ITER: usize = 7;
 
+#[derive(Error, Debug)]
+pub enum PlannerError {
+    #[error("the executive planner did not produce a report: {0}")]
+    NoReport(String),
+}
+
 pub struct ExecutivePlanner<'b> {
     openai_key: &'b String,
     model_description: &'b String,
     math_notes: &'b String,
+    display_unit: LengthUnit,
+    revision_notes: String,
+    existing_model: String,
+    /// Features already in the part studio, which the plan may target
+    existing_features: Vec<String>,
 }
 
 impl<'b> ExecutivePlanner<'b> {
@@ -107,24 +172,40 @@ impl<'b> ExecutivePlanner<'b> {
         openai_key: &'b String,
         model_description: &'b String,
         math_notes: &'b String,
+        display_unit: LengthUnit,
     ) -> Result<ExecutivePlanner<'b>, Box<dyn Error>> {
         Ok(ExecutivePlanner {
             openai_key,
             model_description,
             math_notes,
+            display_unit,
+            revision_notes: String::new(),
+            existing_model: String::new(),
+            existing_features: Vec::new(),
         })
     }
 
-    async fn process_user_input_tool<'a, I>(
+    /// Asks the planner to revise an earlier outline with the user's feedback
+    pub fn with_revision(mut self, previous_outline: &str, feedback: &str) -> Self {
+        self.revision_notes = REVISION_NOTES
+            .replace("{{previous_outline}}", previous_outline)
+            .replace("{{feedback}}", feedback);
+        self
+    }
+
+    /// Plans a change to the model described in `section`, which has the
+    /// features named in `features`
+    pub fn with_existing_model(mut self, section: String, features: Vec<String>) -> Self {
+        self.existing_model = section;
+        self.existing_features = features;
+        self
+    }
+
+    async fn process_user_input_tool(
         &mut self,
         output: &str,
-        get_input: &I,
-    ) -> Result<String, Box<dyn Error>>
-    where
-        I: Fn(String) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn Error>>> + Send + 'a>>
-            + Send
-            + 'a,
-    {
+        io: &SessionIo<'_>,
+    ) -> Result<String, Box<dyn Error>> {
         let mut response = deserialize_output(output)?;
         let input: UserQueryInput =
             serde_yaml::from_value(response.clone().input).inspect_err(|e| {
@@ -134,39 +215,31 @@ impl<'b> ExecutivePlanner<'b> {
                 )
             })?;
         let prompt = input.question.replace("\"", "");
-        let real_user_input = get_input(prompt).await?;
+        let real_user_input = io.ask(&prompt).await?;
 
-        response.output = real_user_input;
+        response.output = normalize_dimensions(&real_user_input, self.display_unit);
 
         Ok(serde_yaml::to_string(&response)?)
     }
 
-    fn process_report_tool(&self, output: &str) -> String {
-        let output = *output
-            .split("content:")
-            .collect::<Vec<&str>>()
-            .last()
-            .expect("No report content?");
-        let mut output = output.replace("output: none: null", "").trim().to_owned();
-
-        if output.starts_with("|") {
-            output = output.replacen("|", "", 1);
-        }
-
-        output
+    /// Extracts and validates the model plan from a Report invocation
+    fn process_report_tool(
+        &self,
+        invocation: &str,
+        capabilities: &CapabilityRegistry,
+    ) -> Result<ModelPlan, Box<dyn Error>> {
+        let invocation = deserialize_invocation(invocation)?;
+        let input: ReportInput = serde_yaml::from_value(invocation.input)?;
+        validate_plan(input.plan, capabilities, &self.existing_features)
     }
 
-    pub async fn run<'a, I>(&mut self, get_input: &I) -> Result<String, Box<dyn Error>>
-    where
-        I: Fn(String) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn Error>>> + Send + 'a>>
-            + Send
-            + 'a,
-    {
+    pub async fn run(&mut self, io: &SessionIo<'_>) -> Result<ModelPlan, Box<dyn Error>> {
         let mut tool_collection: ToolCollection<Multitool> = ToolCollection::new();
         tool_collection.add_tool(UserQuery::new().into());
         tool_collection.add_tool(Report::new().into());
 
         let tool_prompt = tool_collection.to_prompt_template()?;
+        let capabilities = capabilities::registry().await;
         let mut scratchpad = String::new();
 
         let opts = options! {
@@ -176,11 +249,24 @@ impl<'b> ExecutivePlanner<'b> {
         };
         let exec = executor!(chatgpt, opts)?;
 
-        for _ in 0..MAX_ITER {
+        // The extra, final iteration forces the model to submit its report
+        for iteration in 0..=MAX_ITER {
+            let final_iteration = iteration == MAX_ITER;
+            let final_notice = if final_iteration {
+                FINAL_ITERATION_NOTICE
+            } else {
+                ""
+            };
+
             let parameters = parameters!(
                 "model_description" => self.model_description,
                 "math_notes" => self.math_notes,
+                "revision_notes" => &self.revision_notes,
+                "existing_model" => &self.existing_model,
+                "capabilities" => capabilities.planner_section(),
                 "tools" => tool_prompt.to_string(),
+                "plan_format" => PLAN_FORMAT,
+                "final_notice" => final_notice,
                 "scratchpad" => scratchpad.clone(),
             );
             let res = prompt!(EXECUTIVE_PLANNER_PROMPT)
@@ -201,11 +287,27 @@ impl<'b> ExecutivePlanner<'b> {
                         .replace("```", "");
 
                     if addition.contains("command: Report") {
-                        return Ok(self.process_report_tool(&addition));
+                        match self.process_report_tool(&res, capabilities) {
+                            Ok(plan) => return Ok(plan),
+                            Err(err) => {
+                                eprintln!("Planner submitted an invalid plan: {}", err);
+                                let err = err.to_string().replace('\n', "\n# ");
+                                scratchpad.push_str(&format!(
+                                    concat!(
+                                        "\n{}\n",
+                                        "# PLAN ERROR: The plan was rejected:\n# {}\n",
+                                        "# Fix every problem above and invoke the Report tool again.\n"
+                                    ),
+                                    res.trim(),
+                                    err
+                                ));
+                                continue;
+                            }
+                        }
                     }
 
-                    if addition.contains("command: User Query") {
-                        addition = self.process_user_input_tool(&addition, get_input).await?;
+                    if addition.contains("command: User Query") && !final_iteration {
+                        addition = self.process_user_input_tool(&addition, io).await?;
                     }
 
                     println!(
@@ -251,6 +353,57 @@ impl<'b> ExecutivePlanner<'b> {
             };
         }
 
-        Ok("temp".to_owned())
+        eprintln!(
+            "Executive planner never invoked the Report tool; synthesizing a fallback report"
+        );
+        self.fallback_report(&scratchpad).await
+    }
+
+    /// Writes a report from the scratchpad when the planner never submitted one
+    async fn fallback_report(&self, scratchpad: &str) -> Result<ModelPlan, Box<dyn Error>> {
+        let opts = options! {
+            Model: Model::Other("gpt-4o".to_string()),
+            ApiKey: self.openai_key.clone()
+        };
+        let exec = executor!(chatgpt, opts)?;
+        let capabilities = capabilities::registry().await;
+
+        let parameters = parameters!(
+            "model_description" => self.model_description,
+            "math_notes" => self.math_notes,
+            "revision_notes" => &self.revision_notes,
+            "existing_model" => &self.existing_model,
+            "capabilities" => capabilities.planner_section(),
+            "plan_format" => PLAN_FORMAT,
+            "scratchpad" => scratchpad,
+        );
+
+        let report = prompt!(FALLBACK_REPORT_PROMPT)
+            .run(&parameters, &exec)
+            .await
+            .map_err(|err| PlannerError::NoReport(err.to_string()))?
+            .to_immediate()
+            .await
+            .map_err(|err| PlannerError::NoReport(err.to_string()))?
+            .primary_textual_output()
+            .unwrap_or_default();
+
+        let report = trim_assistant_prefix(&report)
+            .replace("```yaml", "")
+            .replace("```", "");
+        if report.trim().is_empty() {
+            return Err(Box::new(PlannerError::NoReport(
+                "the fallback report was empty".to_owned(),
+            )));
+        }
+
+        let plan = serde_yaml::from_str(&report)
+            .map_err(|err| err.into())
+            .and_then(|plan| validate_plan(plan, capabilities, &self.existing_features))
+            .map_err(|err| {
+                PlannerError::NoReport(format!("the fallback plan was invalid: {err}"))
+            })?;
+
+        Ok(plan)
     }
 }
@@ -1,14 +1,206 @@
+use llm_chain::prompt;
+use llm_chain::tools::ToolUseError;
+use llm_chain::{executor, options, parameters, tools::ToolCollection};
+use llm_chain_openai::chatgpt::Model;
+use std::error::Error;
+
+use crate::chain::tools::calculator_tool::{
+    CalculatorError, CalculatorInput, CalculatorOutput, CalculatorTool,
+};
+use crate::chain::tools::math_notes_tool::{
+    MathNotes, MathNotesError, MathNotesInput, MathNotesOutput,
+};
+use crate::chain::tools::misc::deserialize_invocation;
+use crate::chain::util::trim_assistant_prefix;
+
+use async_trait::async_trait;
+use llm_chain::{
+    multitool,
+    tools::ToolDescription,
+    tools::{Tool, ToolError},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+multitool!(
+    Multitool,
+    MultiToolInput,
+    MultiToolOutput,
+    MultitoolError,
+    CalculatorTool,
+    CalculatorInput,
+    CalculatorOutput,
+    CalculatorError,
+    MathNotes,
+    MathNotesInput,
+    MathNotesOutput,
+    MathNotesError
+);
+
+const MATHEMATICIAN_PROMPT: &str = "\
+You are a meticulous mechanical engineer. A colleague is about to plan how to
+create the following model in a parametric CAD program. Before they start,
+work out every number they will need, such as:
+- Coordinates of polygon vertices
+- Positions of holes on a bolt circle
+- Sizes of loft sections
+- Clearances, wall thicknesses, and offsets between features
+
+The description of the model to create is:
+```txt
+{{model_description}}
+```
+
+All lengths are in Inches and all angles are in degrees. Shapes are centered
+on the origin unless the description says otherwise.
+
+You MUST use the Calculator tool for every computation, no matter how simple.
+Never do arithmetic in your head, and only report values the Calculator gave
+you. If the model needs no calculations, submit notes saying so.
+
+When you are done, submit your notes with the Math Notes tool.
+
+================
+
+{{tools}}
+
+You are encouraged to explain your thoughts as much as possible. Prefix
+all thoughts with a YAML comment (i.e., a line that begins with #)
+
+{{final_notice}}
+
+===== PREVIOUS COMMANDS & THOUGHTS =====
+
+```yaml
+{{scratchpad}}
+```
+
+===== NEW COMMANDS & THOUGHTS =====
+
+```yaml
+";
+
+const FINAL_ITERATION_NOTICE: &str = "\
+IMPORTANT: You are out of time. In this response you MUST invoke the Math
+Notes tool with everything you have calculated so far.
+";
+
+const MAX_ITER: usize = 6;
+
 pub struct MathematicianAgent<'a> {
-    _openai_key: &'a String,
+    openai_key: &'a String,
+    model_description: &'a String,
 }
+
 impl<'a> MathematicianAgent<'a> {
-    pub fn new(openai_key: &String) -> MathematicianAgent {
+    pub fn new(openai_key: &'a String, model_description: &'a String) -> MathematicianAgent<'a> {
         MathematicianAgent {
-            _openai_key: openai_key,
+            openai_key,
+            model_description,
         }
     }
 
-    pub async fn run(&self) -> String {
-        "No math notes".to_owned()
+    /// Extracts the notes from a Math Notes invocation
+    fn process_notes_tool(&self, invocation: &str) -> Result<String, Box<dyn Error>> {
+        let invocation = deserialize_invocation(invocation)?;
+        let input: MathNotesInput = serde_yaml::from_value(invocation.input)?;
+        Ok(input.notes)
+    }
+
+    pub async fn run(&self) -> Result<String, Box<dyn Error>> {
+        let mut tool_collection: ToolCollection<Multitool> = ToolCollection::new();
+        tool_collection.add_tool(CalculatorTool::new().into());
+        tool_collection.add_tool(MathNotes::new().into());
+
+        let tool_prompt = tool_collection.to_prompt_template()?;
+        let mut scratchpad = String::new();
+        let mut calculations: Vec<String> = Vec::new();
+
+        let opts = options! {
+            Model: Model::Other("gpt-4o".to_string()),
+            ApiKey: self.openai_key.clone()
+        };
+        let exec = executor!(chatgpt, opts)?;
+
+        // The extra, final iteration forces the model to submit its notes
+        for iteration in 0..=MAX_ITER {
+            let final_notice = if iteration == MAX_ITER {
+                FINAL_ITERATION_NOTICE
+            } else {
+                ""
+            };
+
+            let parameters = parameters!(
+                "model_description" => self.model_description,
+                "tools" => tool_prompt.to_string(),
+                "final_notice" => final_notice,
+                "scratchpad" => scratchpad.clone(),
+            );
+            let res = prompt!(MATHEMATICIAN_PROMPT)
+                .run(&parameters, &exec)
+                .await?
+                .to_immediate()
+                .await?
+                .primary_textual_output()
+                .unwrap_or_default();
+            let res = trim_assistant_prefix(&res)
+                .replace("```yaml", "")
+                .replace("```", "");
+
+            match tool_collection.process_chat_input(&res).await {
+                Ok(new) => {
+                    if res.contains("command: Math Notes") {
+                        match self.process_notes_tool(&res) {
+                            Ok(notes) => {
+                                println!("Mathematician notes:\n{}", notes);
+                                return Ok(notes);
+                            }
+                            Err(err) => {
+                                eprintln!("Mathematician submitted malformed notes: {}", err);
+                                scratchpad.push_str(&format!(
+                                    "\n{}\n# ERROR: The notes could not be read: {}\n",
+                                    res.trim(),
+                                    err
+                                ));
+                                continue;
+                            }
+                        }
+                    }
+
+                    let new = new.replace("result:", "");
+                    calculations.push(new.trim().to_owned());
+                    let addition = format!("\n{}\noutput: {}\n", res.trim(), new.trim());
+
+                    println!(
+                        concat!(
+                            "====SCRATCHPAD NEW====\n",
+                            "{}\n",
+                            "======================\n",
+                        ),
+                        addition
+                    );
+
+                    scratchpad.push_str(&addition);
+                }
+                Err(ToolUseError::NoToolInvocation) => {
+                    scratchpad.push_str(&format!("\n{}", res));
+                }
+                Err(e) => {
+                    eprintln!("Mathematician produced invalid YAML: {}", e);
+                    scratchpad.push_str(&format!(
+                        "\n# YAML ERROR: {}\n# Rephrase the following as valid YAML:\n{}\n",
+                        e, res
+                    ));
+                }
+            }
+        }
+
+        // Fall back to the raw calculator results, which are still exact
+        eprintln!("Mathematician never submitted notes; using its calculations instead");
+        if calculations.is_empty() {
+            Ok("No math notes".to_owned())
+        } else {
+            Ok(format!("Calculated values:\n{}", calculations.join("\n")))
+        }
     }
 }
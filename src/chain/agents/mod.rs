@@ -2,5 +2,6 @@ pub mod executive_planner;
 pub mod mathematician;
 pub mod onpy_agent;
 pub mod pessimist;
+pub mod post_build_reporter;
 pub mod preliminary_reporter;
 pub mod types;
@@ -1,17 +1,32 @@
-use futures::Future;
 use llm_chain::options;
 use llm_chain::prompt;
 use llm_chain::{executor, parameters};
 use llm_chain_openai;
 use llm_chain_openai::chatgpt::Model;
-use std::pin::Pin;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::PathBuf;
 use std::process::{Command, Output};
 use thiserror::Error;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+use crate::chain::capabilities;
+use crate::chain::checkpoint::{BuildVersion, Checkpointer, OnPyProgress, SessionState};
+use crate::chain::fake_onpy::{fake_onpy_dir, read_trace, ExecutionBackend, OnPyTrace, TRACE_FILE};
+use crate::chain::onshape::{measure_parts, remove_added_features, BuildTarget};
+use crate::chain::session_io::SessionIo;
+use crate::chain::units::{normalize_dimensions, LengthUnit};
+use crate::chain::verification::{verify, verify_attempt, BuildGeometry, ExpectedGeometry};
+use crate::server::types::{ApiCredentials, ReviewAction, ServerResponse, ServerResponseType};
+
+/// Where the OnPy guide is fetched from, unless ONPY_GUIDE_URL says otherwise
+const DEFAULT_ONPY_GUIDE_URL: &str =
+    "https://raw.githubusercontent.com/kyle-tennison/onpy/main/guide.md";
 const MAX_ITER: usize = 10;
 const MAX_ITER_ERR: usize = 10;
+/// Rebuilds allowed when a build doesn't match the plan, before asking the user
+const MAX_VERIFY_RETRIES: usize = 2;
 const ONPY_AGENT_PROMPT: &str = r###"
 
 Use OnPy (described below) to create a 3D model to conform to the user's
@@ -27,7 +42,9 @@ request.
 - When possible, it is best to use an offset plane for sketches instead of
     trying to reference other parts.
 
+{{capabilities}}
 ===== END DOCUMENTATION =====
+{{existing_model}}
 
 The original user's request was:
 {{user_request}}
@@ -41,9 +58,7 @@ will cause an error.
 
 This block is appended to the beginning of your code at runtime:
 ```py
-import onpy
-partstudio = onpy.get_document("{{document_id}}").get_partstudio()
-```
+{{preamble}}```
 
 ===== BEGIN =====
 
@@ -63,7 +78,10 @@ request.
 
 ===== ONPY DOCUMENTATION =====
 {{onpy_guide}}
+
+{{capabilities}}
 ===== END DOCUMENTATION =====
+{{existing_model}}
 
 The original user's request was:
 {{user_request}}
@@ -87,9 +105,7 @@ or create a new document/partstudio.
 More specifically, the following code is appended to the beginning of each
 block at runtime.
 ```py
-import onpy
-partstudio = onpy.get_document("{{document_id}}").get_partstudio()
-```
+{{preamble}}```
 
 ==== BEGIN ====
 
@@ -97,17 +113,39 @@ partstudio = onpy.get_document("{{document_id}}").get_partstudio()
 
 "###;
 
-const INPUT_PRASE_PROMPT: &str = r###"\
+const FEEDBACK_PROMPT: &str = r###"
+A user was shown a 3D model and asked if it meets their specifications.
 
-The following response is from a user when asked if they want changes to their
-model.
-
-The response was:
+They responded:
 {{user_response}}
 
-If the user wants changes, respond "Yes"
-If the user does NOT want changes, response "No"
-Respond in only ONE word.
+Classify the response. Respond with YAML in exactly ONE of these forms:
+
+```yaml
+decision: accept    # the user is happy with the model as it is
+```
+
+```yaml
+decision: change    # the user wants something changed
+change: Make the mug 2 inches taller
+```
+
+```yaml
+decision: undo      # the user wants the previous version back
+```
+
+```yaml
+decision: revert_to # the user wants a specific earlier version back
+iteration: 2
+```
+
+```yaml
+decision: stop      # the user wants to stop without further changes
+```
+
+For a change, write the concrete change the user asked for as a single
+instruction. A response that approves the model but also asks for something
+(e.g., "yes, but make it taller") is a change, not an acceptance.
 
 "###;
 
@@ -121,6 +159,107 @@ pub enum CodeError {
 
     #[error("an internal, unexpected error occurred while parsing Python: {0}")]
     Internal(String),
+
+    #[error("gave up after {attempts} attempts to fix the code; the last error was: {last_error}")]
+    RepairsExhausted { attempts: usize, last_error: String },
+}
+
+/// What the user wants to do after the agent fails to repair its code
+enum RepairChoice {
+    /// Try again, with the user's guidance if they gave any
+    Retry(String),
+    Simplify,
+    Stop,
+}
+
+impl RepairChoice {
+    /// Reads a typed reply. Returns `None` if it isn't one of the options.
+    fn parse(reply: &str) -> Option<RepairChoice> {
+        let reply = reply.trim();
+        let lowered = reply.to_lowercase();
+        if lowered == "3" || lowered.starts_with("stop") {
+            Some(RepairChoice::Stop)
+        } else if lowered == "2" || lowered.starts_with("simplify") {
+            Some(RepairChoice::Simplify)
+        } else if lowered == "1" {
+            Some(RepairChoice::Retry(String::new()))
+        } else if lowered.starts_with("retry") {
+            let guidance = reply["retry".len()..].trim_start_matches([':', ',']);
+            Some(RepairChoice::Retry(guidance.trim().to_owned()))
+        } else {
+            None
+        }
+    }
+
+    /// The choice of a client that answered with an explicit action. Going
+    /// back to an earlier version isn't an option here.
+    fn from_action(action: ReviewAction, response: &str) -> Option<RepairChoice> {
+        match action {
+            ReviewAction::Approve => Some(RepairChoice::Retry(String::new())),
+            ReviewAction::RequestChanges | ReviewAction::Edit => {
+                Some(RepairChoice::Retry(response.trim().to_owned()))
+            }
+            ReviewAction::Stop => Some(RepairChoice::Stop),
+            ReviewAction::Undo | ReviewAction::RevertTo { .. } => None,
+        }
+    }
+}
+
+/// How the user responded to a finished build
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+enum BuildFeedback {
+    Accept,
+    Change { change: String },
+    Undo,
+    RevertTo { iteration: usize },
+    Stop,
+}
+
+impl BuildFeedback {
+    /// Reads a classifier response. Anything unreadable is kept as a change
+    /// request, so a model is never accepted by mistake.
+    fn parse(response: &str, user_response: &str) -> BuildFeedback {
+        let response = response.replace("```yaml", "").replace("```", "");
+        match serde_yaml::from_str(&response) {
+            Ok(BuildFeedback::Change { change }) if change.trim().is_empty() => {
+                BuildFeedback::Change {
+                    change: user_response.to_owned(),
+                }
+            }
+            Ok(feedback) => feedback,
+            Err(err) => {
+                eprintln!("Feedback classification was malformed: {}", err);
+                BuildFeedback::Change {
+                    change: user_response.to_owned(),
+                }
+            }
+        }
+    }
+
+    /// Feedback from a client that answered with an explicit action
+    fn from_action(action: ReviewAction, response: &str) -> BuildFeedback {
+        match action {
+            ReviewAction::Approve => BuildFeedback::Accept,
+            ReviewAction::RequestChanges | ReviewAction::Edit => BuildFeedback::Change {
+                change: response.trim().to_owned(),
+            },
+            ReviewAction::Undo => BuildFeedback::Undo,
+            ReviewAction::RevertTo { iteration } => BuildFeedback::RevertTo { iteration },
+            ReviewAction::Stop => BuildFeedback::Stop,
+        }
+    }
+
+    /// Annotates the dimensions in a requested change, reading bare numbers
+    /// in `display_unit`
+    fn normalized(self, display_unit: LengthUnit) -> BuildFeedback {
+        match self {
+            BuildFeedback::Change { change } => BuildFeedback::Change {
+                change: normalize_dimensions(&change, display_unit),
+            },
+            feedback => feedback,
+        }
+    }
 }
 
 unsafe impl std::marker::Send for CodeError {}
@@ -128,30 +267,100 @@ unsafe impl Sync for CodeError {}
 
 pub struct OnPyAgent<'b> {
     report: String,
-    openai_key: &'b String,
+    credentials: &'b ApiCredentials,
     original_request: String,
-    onshape_document: String,
+    target: BuildTarget,
+    existing_model: String,
+    expected_geometry: Option<ExpectedGeometry>,
+    backend: ExecutionBackend,
+    display_unit: LengthUnit,
+    /// Where the session's scripts, and their traces, are written
+    workdir: PathBuf,
 }
 
 impl<'b> OnPyAgent<'b> {
     pub fn new(
-        openai_key: &'b String,
+        credentials: &'b ApiCredentials,
         report: String,
         original_request: String,
-        onshape_document: String,
-    ) -> OnPyAgent {
+        target: BuildTarget,
+    ) -> OnPyAgent<'b> {
         OnPyAgent {
-            openai_key,
+            credentials,
             report,
             original_request,
-            onshape_document,
+            target,
+            existing_model: String::new(),
+            expected_geometry: None,
+            backend: ExecutionBackend::default(),
+            display_unit: LengthUnit::Inch,
+            workdir: std::env::temp_dir().join(format!("polybrain-{}", uuid::Uuid::new_v4())),
         }
     }
 
+    /// Runs scripts in `backend` instead of the user's Onshape document
+    pub fn with_backend(mut self, backend: ExecutionBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Reads bare numbers in the user's corrections as `display_unit`
+    pub fn with_display_unit(mut self, display_unit: LengthUnit) -> Self {
+        self.display_unit = display_unit;
+        self
+    }
+
+    /// Checks each build against what the plan should produce
+    pub fn with_expected_geometry(mut self, expected: Option<ExpectedGeometry>) -> Self {
+        self.expected_geometry = expected;
+        self
+    }
+
+    /// Measures the parts in the document and compares them to the plan.
+    /// Fake builds are checked from their trace instead, and have no
+    /// measurements. Nothing is checked if the build couldn't be measured.
+    async fn verify_build(
+        &self,
+        trace: Option<&OnPyTrace>,
+    ) -> (Option<BuildGeometry>, Vec<String>) {
+        if self.backend == ExecutionBackend::FakeOnpy {
+            let attempted = trace.and_then(|trace| ExpectedGeometry::from_plan(&trace.model));
+            let mismatches = match trace {
+                Some(_) => verify_attempt(attempted.as_ref(), self.expected_geometry.as_ref()),
+                None => Vec::new(),
+            };
+            return (None, mismatches);
+        }
+
+        match measure_parts(self.credentials, &self.target).await {
+            Ok(geometry) => {
+                let mismatches = verify(&geometry, self.expected_geometry.as_ref());
+                (Some(geometry), mismatches)
+            }
+            Err(err) => {
+                eprintln!("Failed to measure the build: {err}");
+                (None, Vec::new())
+            }
+        }
+    }
+
+    /// Where the fake onpy package writes the trace of the last script
+    fn trace_path(&self) -> PathBuf {
+        self.workdir.join(TRACE_FILE)
+    }
+
+    /// Builds on top of the model described in `section`
+    pub fn with_existing_model(mut self, section: String) -> Self {
+        self.existing_model = section;
+        self
+    }
+
     async fn load_onpy_guide() -> String {
+        let url =
+            std::env::var("ONPY_GUIDE_URL").unwrap_or_else(|_| DEFAULT_ONPY_GUIDE_URL.to_owned());
         let client = reqwest::Client::new();
         client
-            .get("https://raw.githubusercontent.com/kyle-tennison/onpy/main/guide.md")
+            .get(url)
             .send()
             .await
             .expect("Error in requesting OnPy guide")
@@ -186,17 +395,33 @@ impl<'b> OnPyAgent<'b> {
         Ok(code)
     }
 
-    pub async fn execute_block(code: &str, onshape_document: &str) -> Result<String, CodeError> {
-        let code = format!(
-            concat!(
-                "import onpy\n",
-                "partstudio = onpy.get_document('{doc_id}').get_partstudio()\n",
-                "partstudio.wipe()\n",
-                "{code}"
-            ),
-            doc_id = onshape_document,
-            code = code
-        );
+    /// The last line of a Python traceback, which names the error
+    fn summarize_traceback(traceback: &str) -> String {
+        traceback
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("Unknown error")
+            .trim()
+            .to_owned()
+    }
+
+    /// Runs a script in the target part studio, rebuilding it from scratch.
+    /// The wipe only reaches the user's own features if the session allowed it;
+    /// when editing, only the session's features are removed.
+    pub async fn execute_block(&self, code: &str) -> Result<String, CodeError> {
+        let reset = match (&self.target.kept_features, self.backend) {
+            (Some(kept_features), ExecutionBackend::Onshape) => {
+                remove_added_features(self.credentials, &self.target, kept_features)
+                    .await
+                    .map_err(|err| CodeError::Internal(err.to_string()))?;
+                ""
+            }
+            // The fake part studio starts empty on every run
+            (Some(_), ExecutionBackend::FakeOnpy) => "",
+            (None, _) => "partstudio.wipe()\n",
+        };
+        let code = format!("{}{}{}", self.target.preamble(), reset, code);
 
         println!(
             concat!(
@@ -208,7 +433,13 @@ impl<'b> OnPyAgent<'b> {
         );
 
         // Create a temporary file
-        let mut file = File::create("temp_script.py")
+        tokio::fs::create_dir_all(&self.workdir)
+            .await
+            .map_err(|err| {
+                CodeError::Internal(format!("Failed to create {:?}: {}", self.workdir, err))
+            })?;
+        let script_path = self.workdir.join("temp_script.py");
+        let mut file = File::create(&script_path)
             .await
             .expect("Failed to create tmp python file");
 
@@ -218,12 +449,21 @@ impl<'b> OnPyAgent<'b> {
             .expect("Failed to write to tmp python file");
 
         // Execute the Python script
-        let output: Output = Command::new("python")
-            .arg("temp_script.py")
-            .output()
-            .map_err(|e| {
-                CodeError::ExecutionError(format!("Failed to execute Python script: {:?}", e))
-            })?;
+        let mut command = Command::new("python");
+        command
+            .arg(&script_path)
+            .env("ONSHAPE_DEV_ACCESS", &self.credentials.onshape_access_key)
+            .env("ONSHAPE_DEV_SECRET", &self.credentials.onshape_secret_key);
+        if self.backend == ExecutionBackend::FakeOnpy {
+            // A trace left by an earlier script must not be mistaken for this one's
+            let _ = tokio::fs::remove_file(self.trace_path()).await;
+            command
+                .env("PYTHONPATH", fake_onpy_dir())
+                .env("ONPY_TRACE_FILE", self.trace_path());
+        }
+        let output: Output = command.output().map_err(|e| {
+            CodeError::ExecutionError(format!("Failed to execute Python script: {:?}", e))
+        })?;
 
         // Handle the output
         if output.status.success() {
@@ -243,19 +483,23 @@ impl<'b> OnPyAgent<'b> {
         let opts = options! {
             Model: Model::Other("gpt-4o".to_string()),
             // Model: Model::Gpt35Turbo,
-            ApiKey: self.openai_key.clone(),
+            ApiKey: self.credentials.openai_token.clone(),
             StopSequence: vec!["```\n\n".to_string(), "Cell Output".to_string(), "Console Output".to_string()]
         };
         let exec = executor!(chatgpt, opts).map_err(|err| CodeError::Internal(err.to_string()))?;
         let onpy_guide = Self::load_onpy_guide().await;
+        let capabilities = capabilities::registry().await.coding_section();
         let mut scratchpad = String::new();
+        let mut last_error = error_output.clone();
 
         for _ in 0..MAX_ITER_ERR {
             let parameters = parameters!(
                 "onpy_guide" => &onpy_guide,
+                "capabilities" => &capabilities,
+                "existing_model" => &self.existing_model,
                 "user_request" => &self.original_request,
                 "erroneous_code" => &erroneous_code,
-                "document_id" => &self.onshape_document,
+                "preamble" => self.target.preamble(),
                 "console_output" => &error_output,
                 "scratchpad" => &scratchpad,
             );
@@ -275,12 +519,12 @@ impl<'b> OnPyAgent<'b> {
             let mut code_output = prompt!(ONPY_ERROR_PROMPT)
                 .run(&parameters, &exec)
                 .await
-                .expect("Failed to run handle_error agent")
+                .map_err(|err| CodeError::Internal(err.to_string()))?
                 .to_immediate()
                 .await
-                .expect("Failed to convert LLM response to immediate")
+                .map_err(|err| CodeError::Internal(err.to_string()))?
                 .primary_textual_output()
-                .expect("No LLM output");
+                .unwrap_or_default();
 
             println!(
                 concat!(
@@ -297,58 +541,246 @@ impl<'b> OnPyAgent<'b> {
             code_output = Self::format_code_output(&code_output)
                 .map_err(|err| CodeError::BadFormat(err.to_string()))?;
 
-            match Self::execute_block(&code_output, &self.onshape_document).await {
+            match self.execute_block(&code_output).await {
                 Ok(console) => {
                     return Ok((code_output, console));
                 }
-                Err(err) => scratchpad.push_str(&format!("Cell Error:\n```\n{}\n```", err)),
+                Err(err) => {
+                    scratchpad.push_str(&format!("Cell Error:\n```\n{}\n```", err));
+                    last_error = match err {
+                        CodeError::ExecutionError(traceback) => traceback,
+                        err => err.to_string(),
+                    };
+                }
             };
         }
 
         eprintln!("Max error retries exceeded!");
-        todo!()
+        Err(CodeError::RepairsExhausted {
+            attempts: MAX_ITER_ERR,
+            last_error,
+        })
     }
 
-    pub async fn run<'a, I>(&mut self, get_input: &I) -> Result<(), Box<dyn std::error::Error>>
-    where
-        I: Fn(
-                String,
-            ) -> Pin<
-                Box<dyn Future<Output = Result<String, Box<dyn std::error::Error>>> + Send + 'a>,
-            > + Send
-            + 'a,
-    {
+    /// Restores the last script that built successfully, explains the failure,
+    /// and asks the user how to continue
+    async fn recover_from_failure(
+        &self,
+        io: &SessionIo<'_>,
+        last_good_script: &str,
+        error: &str,
+    ) -> Result<RepairChoice, Box<dyn std::error::Error>> {
+        // Failed attempts may have built part of the model before erroring,
+        // so the part studio is always reset to a known state
+        let (restore, restored, not_restored) = if last_good_script.is_empty() {
+            (
+                "",
+                "Nothing has built successfully yet, so I've removed what the failed attempts added.",
+                "I couldn't remove what the failed attempts added to your document.",
+            )
+        } else {
+            (
+                last_good_script,
+                "I've restored the last version of your model that built successfully.",
+                "I couldn't restore the last working version of your model.",
+            )
+        };
+        match self.execute_block(restore).await {
+            Ok(_) => io.notify(restored).await?,
+            Err(err) => {
+                eprintln!("Failed to restore the last good script: {err}");
+                io.notify(not_restored).await?
+            }
+        }
+
+        let question = format!(
+            concat!(
+                "I couldn't fix an error while building your model:\n{}\n\n",
+                "How would you like to continue?\n",
+                "1) Retry: tell me what to try differently\n",
+                "2) Simplify: build a simpler version of the model\n",
+                "3) Stop: keep the model as it is"
+            ),
+            Self::summarize_traceback(error)
+        );
+        let request =
+            ServerResponse::new(ServerResponseType::Query, question).with_payload(json!({
+                "error": Self::summarize_traceback(error),
+                "options": ["retry", "simplify", "stop"],
+            }));
+        let mut reply = io.review(request.clone()).await?;
+        loop {
+            let choice = match reply.action {
+                Some(action) => RepairChoice::from_action(action, &reply.response),
+                None => RepairChoice::parse(&reply.response),
+            };
+            if let Some(choice) = choice {
+                return Ok(choice);
+            }
+
+            io.notify("Please answer retry (with what to try differently), simplify, or stop.")
+                .await?;
+            reply = io.review(request.clone()).await?;
+        }
+    }
+
+    /// Asks whether the latest build meets the user's specifications. Undoing
+    /// or reverting is handled here, by rebuilding that version and asking again.
+    async fn collect_feedback(
+        &self,
+        io: &SessionIo<'_>,
+        progress: &mut OnPyProgress,
+    ) -> Result<BuildFeedback, Box<dyn std::error::Error>> {
+        loop {
+            let versions: Vec<usize> = progress.history.iter().map(|v| v.iteration).collect();
+            let request = ServerResponse::new(
+                ServerResponseType::Query,
+                "Does this model meet your specifications?",
+            )
+            .with_payload(json!({
+                "options": ["approve", "request_changes", "undo", "revert_to", "stop"],
+                "versions": versions,
+                "current_iteration": progress.current_iteration,
+            }));
+            let reply = io.review(request).await?;
+
+            let feedback = match reply.action {
+                Some(action) => BuildFeedback::from_action(action, &reply.response),
+                None => self.classify_feedback(&reply.response).await?,
+            };
+            println!("User feedback: {:?}", feedback);
+
+            let target = match feedback {
+                BuildFeedback::Undo => {
+                    let current = progress
+                        .history
+                        .iter()
+                        .position(|v| Some(v.iteration) == progress.current_iteration);
+                    match current {
+                        Some(index) if index > 0 => progress.history[index - 1].iteration,
+                        _ => {
+                            io.notify("There is no earlier version of the model to go back to.")
+                                .await?;
+                            continue;
+                        }
+                    }
+                }
+                BuildFeedback::RevertTo { iteration } => iteration,
+                feedback => return Ok(feedback),
+            };
+
+            self.revert_to(io, progress, target).await?;
+        }
+    }
+
+    /// Rebuilds the version from `iteration` so the document matches it
+    async fn revert_to(
+        &self,
+        io: &SessionIo<'_>,
+        progress: &mut OnPyProgress,
+        iteration: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(version) = progress
+            .history
+            .iter()
+            .find(|v| v.iteration == iteration)
+            .cloned()
+        else {
+            io.notify(&format!("There is no version {} to go back to.", iteration))
+                .await?;
+            return Ok(());
+        };
+
+        match self.execute_block(&version.script).await {
+            Ok(console_output) => {
+                progress.scratchpad.push_str(&format!(
+                    "\nThe user went back to the version from iteration {}:\n{}\n",
+                    version.iteration, version.script
+                ));
+                progress.latest_script = version.script;
+                progress.console_output = console_output;
+                progress.current_iteration = Some(version.iteration);
+                io.notify(&format!(
+                    "I've gone back to version {} of your model.",
+                    version.iteration
+                ))
+                .await?;
+            }
+            Err(err) => {
+                eprintln!("Failed to restore iteration {}: {err}", version.iteration);
+                io.notify(&format!(
+                    "I couldn't restore version {} of your model.",
+                    version.iteration
+                ))
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Works out what a free-text reply to a build is asking for
+    async fn classify_feedback(
+        &self,
+        user_response: &str,
+    ) -> Result<BuildFeedback, Box<dyn std::error::Error>> {
+        let opts = options! {
+            Model: Model::Gpt35Turbo,
+            ApiKey: self.credentials.openai_token.clone()
+        };
+        let exec = executor!(chatgpt, opts)?;
+
+        let response = prompt!(FEEDBACK_PROMPT)
+            .run(
+                &parameters!(
+                    "user_response" => user_response
+                ),
+                &exec,
+            )
+            .await?
+            .to_immediate()
+            .await?
+            .primary_textual_output()
+            .unwrap_or_default();
+
+        Ok(BuildFeedback::parse(&response, user_response))
+    }
+
+    pub async fn run(
+        &mut self,
+        io: &SessionIo<'_>,
+        checkpointer: &Checkpointer,
+        state: &mut SessionState,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         // Setup primary executor
         let opts = options! {
             Model: Model::Other("gpt-4o".to_string()),
             // Model: Model::Gpt35Turbo,
-            ApiKey: self.openai_key.clone(),
+            ApiKey: self.credentials.openai_token.clone(),
             StopSequence: vec!["```\n\n".to_string(), "Cell Output".to_string()]
         };
         let main_exec = executor!(chatgpt, opts)?;
 
-        // Setup secondary executor
-        let opts = options! {
-            Model: Model::Gpt35Turbo,
-            ApiKey: self.openai_key.clone(),
-            StopSequence: vec!["\n".to_string()]
-        };
-        let secondary_exec = executor!(chatgpt, opts)?;
-
         let onpy_guide = Self::load_onpy_guide().await;
-        let mut scratchpad = String::new();
+        let capabilities = capabilities::registry().await.coding_section();
+
+        // Resume from the last checkpointed iteration, if any
+        let mut progress = state.onpy.clone().unwrap_or_default();
+
+        let mut verify_retries = 0;
 
-        for _ in 0..MAX_ITER {
+        for iteration in progress.iteration..MAX_ITER {
             // Generate code
             println!("generating code...");
             let mut code_output = prompt!(ONPY_AGENT_PROMPT)
                 .run(
                     &parameters!(
                         "onpy_guide" => &onpy_guide,
+                        "capabilities" => &capabilities,
+                        "existing_model" => &self.existing_model,
                         "user_request" => &self.original_request,
                         "modeling_instructions" => &self.report,
-                        "document_id" => &self.onshape_document,
-                        "scratchpad" => &scratchpad
+                        "preamble" => self.target.preamble(),
+                        "scratchpad" => &progress.scratchpad
                     ),
                     &main_exec,
                 )
@@ -356,7 +788,7 @@ impl<'b> OnPyAgent<'b> {
                 .to_immediate()
                 .await?
                 .primary_textual_output()
-                .expect("No LLM output");
+                .ok_or("the model returned no code")?;
 
             println!(
                 concat!(
@@ -369,61 +801,331 @@ impl<'b> OnPyAgent<'b> {
             );
 
             // Run code
+            io.notify("Building the model in OnShape...").await?;
             code_output = Self::format_code_output(&code_output)?;
-            match Self::execute_block(&code_output, &self.onshape_document).await {
-                Ok(output) => {
-                    scratchpad.push_str(&code_output);
-                    scratchpad.push_str(&format!("Cell Output:\n```\n{}\n```", output))
-                }
-                Err(CodeError::ExecutionError(tb)) => {
-                    let (new_code, new_output) = &self
-                        .handle_error(code_output.clone(), tb)
+            let (script, console_output) = match self.execute_block(&code_output).await {
+                Ok(output) => (code_output, output),
+                Err(err) => {
+                    let traceback = match err {
+                        CodeError::ExecutionError(traceback) => traceback,
+                        err => err.to_string(),
+                    };
+                    io.notify("The build ran into an error; I'm working on a fix.")
+                        .await?;
+
+                    match self
+                        .handle_error(code_output.clone(), traceback.clone())
                         .await
-                        .inspect_err(|err| {
-                            eprintln!("Failed to recover from erroneous response: {err}")
-                        })?;
-
-                    scratchpad.push_str(new_code);
-                    scratchpad.push_str(&format!("Cell Output:\n```\n{}\n```", new_output));
-                }
-                Err(_) => {
-                    panic!("Unhandled error occurred during code execution")
+                    {
+                        Ok(repaired) => {
+                            progress.repairs.push(Self::summarize_traceback(&traceback));
+                            repaired
+                        }
+                        Err(err) => {
+                            eprintln!("Failed to recover from erroneous response: {err}");
+                            let failure = match err {
+                                CodeError::RepairsExhausted { last_error, .. } => last_error,
+                                err => err.to_string(),
+                            };
+
+                            let choice = self
+                                .recover_from_failure(io, &progress.latest_script, &failure)
+                                .await?;
+                            let stop = matches!(choice, RepairChoice::Stop);
+                            match choice {
+                                RepairChoice::Retry(guidance) => {
+                                    let guidance =
+                                        normalize_dimensions(&guidance, self.display_unit);
+                                    progress.scratchpad.push_str(&format!(
+                                            concat!(
+                                                "\nThe code above failed and could not be fixed. The error was:\n",
+                                                "```\n{}\n```\n",
+                                                "Try a different approach. The user suggests: {}\n"
+                                            ),
+                                            Self::summarize_traceback(&failure),
+                                            if guidance.is_empty() { "nothing" } else { &guidance }
+                                        ));
+                                }
+                                RepairChoice::Simplify => {
+                                    progress.scratchpad.push_str(&format!(
+                                            concat!(
+                                                "\nThe code above failed and could not be fixed. The error was:\n",
+                                                "```\n{}\n```\n",
+                                                "Build a simpler version of the model that leaves out ",
+                                                "whatever caused this error.\n"
+                                            ),
+                                            Self::summarize_traceback(&failure)
+                                        ));
+                                }
+                                RepairChoice::Stop => {
+                                    progress.unresolved_error =
+                                        Some(Self::summarize_traceback(&failure));
+                                }
+                            }
+
+                            progress.iteration = iteration + 1;
+                            state.onpy = Some(progress.clone());
+                            checkpointer.save(state).await;
+
+                            if stop {
+                                break;
+                            }
+                            continue;
+                        }
+                    }
                 }
             };
 
-            // Validate with user
-            let user_input =
-                get_input("Does this model meet your specifications?".to_owned()).await?;
+            io.stream(&console_output).await?;
+            progress.scratchpad.push_str(&script);
+            progress
+                .scratchpad
+                .push_str(&format!("Cell Output:\n```\n{}\n```", console_output));
+
+            if self.backend == ExecutionBackend::FakeOnpy {
+                progress.trace = read_trace(&self.trace_path()).await;
+                if let Some(trace) = &progress.trace {
+                    println!(
+                        "fake build traced {} sketch(es) and {} feature(s){}",
+                        trace.model.sketches.len(),
+                        trace.model.features.len(),
+                        if trace.wiped { " after a wipe" } else { "" }
+                    );
+                }
+            }
 
-            let llm_interpretation = prompt!(INPUT_PRASE_PROMPT)
-                .run(
-                    &parameters!(
-                        "user_response" => &user_input
-                    ),
-                    &secondary_exec,
-                )
-                .await?
-                .to_immediate()
+            // Check the geometry before showing the model to the user
+            let (geometry, mismatches) = self.verify_build(progress.trace.as_ref()).await;
+            progress.geometry = geometry;
+            progress.verification_issues = mismatches;
+            if !progress.verification_issues.is_empty() {
+                let issues = progress
+                    .verification_issues
+                    .iter()
+                    .map(|issue| format!("- {issue}"))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+
+                if verify_retries < MAX_VERIFY_RETRIES {
+                    verify_retries += 1;
+                    io.notify("The model doesn't match the plan yet; I'm fixing it.")
+                        .await?;
+                    progress.scratchpad.push_str(&format!(
+                        concat!(
+                            "\nVERIFICATION FAILED. The code ran, but the model it built ",
+                            "does not match the plan:\n{}\n",
+                            "Fix the code so the model matches the plan.\n"
+                        ),
+                        issues
+                    ));
+
+                    progress.iteration = iteration + 1;
+                    state.onpy = Some(progress.clone());
+                    checkpointer.save(state).await;
+                    continue;
+                }
+
+                io.notify(&format!(
+                    "The model still differs from the plan in some ways:\n{}",
+                    issues
+                ))
+                .await?;
+            }
+            verify_retries = 0;
+            progress.history.push(BuildVersion {
+                iteration: iteration + 1,
+                script: script.clone(),
+            });
+            progress.current_iteration = Some(iteration + 1);
+            progress.latest_script = script;
+            progress.console_output = console_output;
+
+            let feedback = self
+                .collect_feedback(io, &mut progress)
                 .await?
-                .primary_textual_output()
-                .expect("No LLM output");
+                .normalized(self.display_unit);
+
+            if let BuildFeedback::Change { change } = &feedback {
+                let scratchpad_addition = format!(
+                    concat!(
+                        "The user was asked if they are satisfied with the model above. ",
+                        "They asked for the following change:\n\"{}\"\n",
+                        "Make adjustments to the previous model such that it conforms with the user's requested change",
+                    ),
+                    change
+                );
 
-            let is_acceptance = llm_interpretation.to_ascii_lowercase().contains("yes");
+                progress.scratchpad.push_str(&scratchpad_addition);
+                progress.change_requests.push(change.clone());
+            }
 
-            if is_acceptance {
-                println!("The user accepted the model");
-                break;
-            } else {
-                let scratchpad_addition = format!(concat!(
-                    "The user was asked asked if they are satisfied with the model above. ",
-                    "They responded saying: \n\"{}\"\n",
-                    "Make adjustments to the previous model such that it conforms with the user's requested change",
-                ), user_input);
+            progress.iteration = iteration + 1;
+            state.onpy = Some(progress.clone());
+            checkpointer.save(state).await;
 
-                scratchpad.push_str(&scratchpad_addition);
+            match feedback {
+                BuildFeedback::Accept => {
+                    println!("The user accepted the model");
+                    break;
+                }
+                BuildFeedback::Stop => {
+                    println!("The user stopped iterating on the model");
+                    io.notify("Stopping here; your model is left as it is.")
+                        .await?;
+                    break;
+                }
+                _ => {}
             }
         }
 
+        if !progress.latest_script.is_empty() {
+            io.artifact("model.py", &progress.latest_script).await?;
+        }
+
         Ok(())
     }
 }
+
+impl Drop for OnPyAgent<'_> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.workdir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feedback_is_read_from_yaml() {
+        assert_eq!(
+            BuildFeedback::parse("```yaml\ndecision: accept\n```", "looks great"),
+            BuildFeedback::Accept
+        );
+        assert_eq!(
+            BuildFeedback::parse("decision: revert_to\niteration: 2", "go back"),
+            BuildFeedback::RevertTo { iteration: 2 }
+        );
+        assert_eq!(
+            BuildFeedback::parse("decision: change\nchange: Make it taller", "taller"),
+            BuildFeedback::Change {
+                change: "Make it taller".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn requested_changes_are_normalized() {
+        assert_eq!(
+            BuildFeedback::Change {
+                change: "make it 25 mm wide".to_owned()
+            }
+            .normalized(LengthUnit::Inch),
+            BuildFeedback::Change {
+                change: "make it 25 mm (0.9843 in) wide".to_owned()
+            }
+        );
+        assert_eq!(
+            BuildFeedback::from_action(ReviewAction::RequestChanges, "25")
+                .normalized(LengthUnit::Millimeter),
+            BuildFeedback::Change {
+                change: "25 mm (0.9843 in)".to_owned()
+            }
+        );
+        assert_eq!(
+            BuildFeedback::Undo.normalized(LengthUnit::Millimeter),
+            BuildFeedback::Undo
+        );
+    }
+
+    #[test]
+    fn unreadable_feedback_is_kept_as_a_change() {
+        let expected = BuildFeedback::Change {
+            change: "add a hole".to_owned(),
+        };
+        assert_eq!(
+            BuildFeedback::parse("I think they want a hole", "add a hole"),
+            expected
+        );
+        assert_eq!(
+            BuildFeedback::parse("decision: change\nchange: ''", "add a hole"),
+            expected
+        );
+    }
+
+    #[test]
+    fn repair_choices_are_read_from_replies() {
+        assert!(matches!(
+            RepairChoice::parse(" 3 "),
+            Some(RepairChoice::Stop)
+        ));
+        assert!(matches!(
+            RepairChoice::parse("Simplify it"),
+            Some(RepairChoice::Simplify)
+        ));
+        assert!(matches!(
+            RepairChoice::parse("retry: use a circle"),
+            Some(RepairChoice::Retry(guidance)) if guidance == "use a circle"
+        ));
+        assert!(RepairChoice::parse("hmm, not sure").is_none());
+    }
+
+    #[test]
+    fn repair_actions_take_precedence_over_text() {
+        assert!(matches!(
+            RepairChoice::from_action(ReviewAction::Stop, "retry"),
+            Some(RepairChoice::Stop)
+        ));
+        assert!(matches!(
+            RepairChoice::from_action(ReviewAction::RequestChanges, " thinner walls "),
+            Some(RepairChoice::Retry(guidance)) if guidance == "thinner walls"
+        ));
+        assert!(RepairChoice::from_action(ReviewAction::Undo, "").is_none());
+    }
+
+    #[tokio::test]
+    async fn fake_builds_are_traced() {
+        let credentials = ApiCredentials {
+            openai_token: String::new(),
+            onshape_access_key: String::new(),
+            onshape_secret_key: String::new(),
+        };
+        let target = BuildTarget {
+            document_id: "0123456789abcdef01234567".to_owned(),
+            workspace_id: None,
+            element_id: None,
+            part_studio_name: None,
+            snapshot_version_id: None,
+            kept_features: None,
+        };
+        let agent = OnPyAgent::new(&credentials, String::new(), String::new(), target)
+            .with_backend(ExecutionBackend::FakeOnpy);
+
+        // Both sketches keep the default name
+        let script = concat!(
+            "base = partstudio.add_sketch(partstudio.features.top_plane)\n",
+            "base.add_corner_rectangle((0, 0), (2, 1))\n",
+            "partstudio.add_extrude(base.faces, 0.5)\n",
+            "plane = partstudio.add_offset_plane(partstudio.features.top_plane, 1)\n",
+            "post = partstudio.add_sketch(plane)\n",
+            "post.add_circle((1, 0.5), 0.25)\n",
+            "partstudio.add_extrude(post.faces, 2)\n",
+        );
+        agent.execute_block(script).await.unwrap();
+        let trace = read_trace(&agent.trace_path()).await.unwrap();
+
+        assert!(trace.wiped);
+        assert_eq!(trace.model.part_count, Some(2));
+        let names: Vec<&str> = trace
+            .model
+            .sketches
+            .iter()
+            .map(|sketch| sketch.name.as_str())
+            .collect();
+        assert_eq!(names, ["#0 New Sketch", "#1 New Sketch"]);
+
+        let expected = ExpectedGeometry::from_plan(&trace.model).unwrap();
+        assert_eq!(expected.part_count, Some(2));
+        assert_eq!(expected.size, [2.0, 1.0, 3.0]);
+    }
+}
@@ -1,6 +1,3 @@
-use std::{error::Error, pin::Pin};
-
-use futures::Future;
 use llm_chain::options;
 use llm_chain::prompt;
 use llm_chain::{
@@ -10,10 +7,13 @@ use llm_chain::{
 use llm_chain_openai;
 use llm_chain_openai::chatgpt::Model;
 
-use crate::{
-    chain::util::trim_assistant_prefix,
-    server::types::{ServerResponse, ServerResponseType},
-};
+use serde::{Deserialize, Serialize};
+
+use crate::chain::capabilities::{self, CapabilityRegistry};
+use crate::chain::checkpoint::{Checkpointer, SessionState};
+use crate::chain::spec::{ModelSpec, WrittenSpec, SPEC_FORMAT};
+use crate::chain::units::{normalize_dimensions, LengthUnit};
+use crate::chain::{session_io::SessionIo, util::trim_assistant_prefix};
 
 const PESSIMIST_PROMPT: &str = r###"
 
@@ -28,85 +28,188 @@ use your existing knowledge of 3D CAD platforms to determine if their
 model can be created within Polybrain's capabilities. When in doubt, 
 let the user do what they want.
 
-Polybrain (a parametric modeler) has the ability to:
-- Create 2D sketches with primitive lines, arcs, rectangles, and circles
-ate extrusions (addition and subtraction)- Cre
-- Create lofts (this is very big!)
-
-This means that Polybrain, unlike other CAD software, is unable to:
-- Create revolve, sweep, and chamfer features
-- Create complex 2D sketches
-- Create angled, complicated faces
-
-The following is your conversation with the user. 
+{{capabilities}}{{existing_model}}
+The following is your conversation with the user.
 If you deny a user's request, tell them exactly why.
-Respond quickly, and try not to ask too many questions. Your responses
+Respond quickly, and try not to ask too many questions. Your messages
 should rarely be longer than 2 sentences.
 
-If the request is reasonable, end your final message with \"Begin!\" You 
-MUST respond with \"Begin!\" eventually. 
+Each response is a decision, written ONLY as YAML in this format:
+```yaml
+decision: ask           # one of ask, accept, reject
+message: |
+  What you say to the user. When asking, this is your question.
+reason: |
+  Only when rejecting: which capability the request needs that Polybrain lacks
+```
+
+Accept as soon as the request is reasonable; when in doubt, accept.
 
-YOU MUST SEND "Begin!" TO ALLOW THE USER TO PROCEED. IF YOU PROMPT NO QUESTION
-YOU MUST SEND "Begin!" IT IS PARAMOUNT!
+{{final_notice}}
 
 {{conversation_history}}
 "###;
 
+const FINAL_TURN_NOTICE: &str = "\
+IMPORTANT: This is your last turn. You MUST accept or reject the request; do
+not ask another question. If details are still missing, accept and make
+reasonable assumptions.
+";
+
 const SUMMARIZER_PROMPT: &str = "
 Consider the following conversation between a user and an assistant. Summarize
 the model that the user ended up requesting in the end. Your summary should
-be no longer than four sentences, but it should include all the details
-available in this conversation. DO NOT include any new features that weren't
-requested by the user.
+include all the details available in this conversation. DO NOT include any new
+features that weren't requested by the user. If the user corrected an earlier
+summary, follow their correction.
 
+Write every dimension with the unit the user gave it in.
+{{existing_model}}
 The conversation is:
 {{conversation_history}}
+
+Respond with the summary only, as YAML in the following format:
+```yaml
+{{spec_format}}
+```
 ";
 
+/// What the pessimist decided to do on its turn
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum PessimistDecision {
+    Ask {
+        message: String,
+    },
+    Accept {
+        message: String,
+    },
+    Reject {
+        message: String,
+        #[serde(default)]
+        reason: String,
+    },
+}
+
+impl PessimistDecision {
+    /// Parses a decision, treating anything that isn't one as a question
+    fn parse(response: &str) -> PessimistDecision {
+        let response = response.replace("```yaml", "").replace("```", "");
+        serde_yaml::from_str(&response).unwrap_or_else(|err| {
+            eprintln!("Pessimist response was not a decision: {}", err);
+            PessimistDecision::Ask {
+                message: response.trim().to_owned(),
+            }
+        })
+    }
+}
+
+/// Who said a message in the conversation with the pessimist
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Speaker {
+    User,
+    Assistant,
+}
+
+/// One message of the conversation with the pessimist, kept so a resumed
+/// session can pick the conversation back up
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TranscriptMessage {
+    pub speaker: Speaker,
+    pub content: String,
+}
+
+/// How the conversation with the pessimist ended
+pub enum PessimistOutcome {
+    Accepted(ModelSpec),
+    Rejected { message: String, reason: String },
+}
+
 pub struct PessimistAgent<'b> {
-    messages: Conversation,
+    transcript: Vec<TranscriptMessage>,
     openai_key: &'b String,
+    display_unit: LengthUnit,
+    max_turns: usize,
+    existing_model: String,
 }
 
 impl<'b> PessimistAgent<'b> {
-    pub fn new(openai_key: &'b String) -> PessimistAgent {
+    pub fn new(
+        openai_key: &'b String,
+        display_unit: LengthUnit,
+        max_turns: usize,
+    ) -> PessimistAgent {
         PessimistAgent {
-            messages: Conversation::new(),
+            transcript: Vec::new(),
             openai_key,
+            display_unit,
+            max_turns: max_turns.max(1),
+            existing_model: String::new(),
         }
     }
 
+    /// Treats the request as a change to the model described in `section`
+    pub fn with_existing_model(mut self, section: String) -> Self {
+        self.existing_model = section;
+        self
+    }
+
+    /// Continues a conversation a previous run of the session left off
+    pub fn with_transcript(mut self, transcript: Vec<TranscriptMessage>) -> Self {
+        self.transcript = transcript;
+        self
+    }
+
+    fn add_message(&mut self, speaker: Speaker, content: String) {
+        self.transcript.push(TranscriptMessage { speaker, content });
+    }
+
     fn build_conversation_history(&self) -> String {
-        let message_history = &self.messages.to_string();
-        message_history.to_owned()
+        let mut messages = Conversation::new();
+        for message in &self.transcript {
+            messages.add_message(match message.speaker {
+                Speaker::User => ChatMessage::user(message.content.clone()),
+                Speaker::Assistant => ChatMessage::assistant(message.content.clone()),
+            });
+        }
+        messages.to_string()
     }
 
-    fn build_prompt(&self) -> String {
-        PESSIMIST_PROMPT.replace(
-            "{{conversation_history}}",
-            &self.build_conversation_history(),
-        )
+    fn build_prompt(&self, capabilities: &CapabilityRegistry, final_turn: bool) -> String {
+        let final_notice = if final_turn { FINAL_TURN_NOTICE } else { "" };
+        PESSIMIST_PROMPT
+            .replace("{{capabilities}}", &capabilities.pessimist_section())
+            .replace("{{existing_model}}", &self.existing_model)
+            .replace("{{final_notice}}", final_notice)
+            .replace(
+                "{{conversation_history}}",
+                &self.build_conversation_history(),
+            )
     }
 
-    pub async fn run<'a, I, O>(
+    /// Talks with the user until the request is accepted or rejected. The
+    /// transcript is checkpointed after every answer.
+    pub async fn run(
         &mut self,
         initial_message: &str,
-        get_input: &I,
-        send_output: &O,
-    ) -> Result<String, Box<dyn std::error::Error>>
-    where
-        I: Fn(String) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn Error>>> + Send + 'a>>
-            + Send
-            + 'a,
-        O: Fn(
-                ServerResponse,
-            ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + Send + 'a>>
-            + Send
-            + 'a,
-    {
-        let mut agent_response: String = "".to_owned();
-        self.messages
-            .add_message(ChatMessage::user(initial_message.to_owned()));
+        io: &SessionIo<'_>,
+        checkpointer: &Checkpointer,
+        state: &mut SessionState,
+    ) -> Result<PessimistOutcome, Box<dyn std::error::Error>> {
+        if self.transcript.is_empty() {
+            self.add_message(
+                Speaker::User,
+                normalize_dimensions(initial_message, self.display_unit),
+            );
+        }
+        // Questions asked before the session was resumed count as turns
+        let turns_taken = self
+            .transcript
+            .iter()
+            .filter(|message| message.speaker == Speaker::Assistant)
+            .count()
+            .min(self.max_turns - 1);
 
         let opts = options! {
             Model: Model::Other("gpt-4o".to_string()),
@@ -115,37 +218,87 @@ impl<'b> PessimistAgent<'b> {
             StopSequence: vec!["User:".to_string()]
         };
         let exec = executor!(chatgpt, opts)?;
+        let capabilities = capabilities::registry().await;
 
-        while !agent_response.contains("Begin!") {
+        for turn in turns_taken + 1..=self.max_turns {
+            let final_turn = turn == self.max_turns;
             let parameters = parameters! {};
 
-            let res = prompt!(system: &self.build_prompt())
+            let res = prompt!(system: &self.build_prompt(capabilities, final_turn))
                 .run(&parameters, &exec) // ...and run it
                 .await?;
 
             let r = res.to_immediate().await?.as_content().to_text().clone();
-            agent_response = trim_assistant_prefix(&r).trim().to_string();
+            let decision = PessimistDecision::parse(trim_assistant_prefix(&r));
 
-            println!("Pessimist: {}", agent_response);
+            println!("Pessimist: {:?}", decision);
 
-            if agent_response.contains("Begin!") {
-                send_output(ServerResponse {
-                    response_type: ServerResponseType::Info,
-                    content: agent_response.replace("Begin!", ""),
-                })
-                .await?;
-            } else {
-                self.messages
-                    .add_message(ChatMessage::assistant(agent_response.replace("\n", " ")));
-                let user_input = get_input(agent_response.clone()).await?;
-                self.messages.add_message(ChatMessage::user(user_input))
+            match decision {
+                PessimistDecision::Accept { message } => {
+                    io.notify(message.trim()).await?;
+                    break;
+                }
+                PessimistDecision::Reject { message, reason } => {
+                    return Ok(PessimistOutcome::Rejected {
+                        message: message.trim().to_owned(),
+                        reason: reason.trim().to_owned(),
+                    });
+                }
+                PessimistDecision::Ask { .. } if final_turn => {
+                    // Out of turns; carry on rather than trapping the user
+                    io.notify(
+                        "I'll go ahead with reasonable assumptions for anything still unclear.",
+                    )
+                    .await?;
+                }
+                PessimistDecision::Ask { message } => {
+                    let message = message.trim();
+                    self.add_message(Speaker::Assistant, message.replace("\n", " "));
+                    let user_input = io.ask(message).await?;
+                    self.add_message(
+                        Speaker::User,
+                        normalize_dimensions(&user_input, self.display_unit),
+                    );
+
+                    state.pessimist_transcript = self.transcript.clone();
+                    checkpointer.save(state).await;
+                }
             }
         }
 
-        // Summarize what the user decided on
+        Ok(PessimistOutcome::Accepted(self.summarize().await?))
+    }
+
+    /// Applies the user's correction to the spec and summarizes again
+    pub async fn revise(
+        &mut self,
+        correction: &str,
+    ) -> Result<ModelSpec, Box<dyn std::error::Error>> {
+        self.add_message(
+            Speaker::User,
+            format!(
+                "Correction to the summary: {}",
+                normalize_dimensions(correction, self.display_unit)
+            ),
+        );
+        self.summarize().await
+    }
+
+    /// Summarizes what the user decided on as a model spec
+    async fn summarize(&self) -> Result<ModelSpec, Box<dyn std::error::Error>> {
+        let opts = options! {
+            Model: Model::Other("gpt-4o".to_string()),
+            ApiKey: self.openai_key.clone()
+        };
+        let exec = executor!(chatgpt, opts)?;
+
         let summary = prompt!(SUMMARIZER_PROMPT)
             .run(
-                &parameters!("conversation_history" => self.build_conversation_history()),
+                &parameters!(
+                    "conversation_history" => self.build_conversation_history(),
+                    "spec_format" => SPEC_FORMAT,
+                    "existing_model" => &self.existing_model,
+                ),
                 &exec,
             )
             .await?
@@ -154,10 +307,20 @@ impl<'b> PessimistAgent<'b> {
             .as_content()
             .to_text();
 
-        let summary = trim_assistant_prefix(&summary).to_owned();
+        let summary = trim_assistant_prefix(&summary)
+            .replace("```yaml", "")
+            .replace("```", "");
 
         println!("Summarized prompt as: {}", summary);
 
-        Ok(summary)
+        let spec = match serde_yaml::from_str::<WrittenSpec>(&summary) {
+            Ok(written) => ModelSpec::from_written(written, self.display_unit),
+            Err(err) => {
+                eprintln!("Summary was not a valid spec; keeping it as prose: {}", err);
+                ModelSpec::from_prose(&summary)
+            }
+        };
+
+        Ok(spec)
     }
 }
@@ -0,0 +1,136 @@
+use llm_chain::options;
+use llm_chain::prompt;
+use llm_chain::{executor, parameters};
+use llm_chain_openai;
+use llm_chain_openai::chatgpt::Model;
+
+use crate::chain::checkpoint::OnPyProgress;
+use crate::chain::units::LengthUnit;
+use crate::chain::util::trim_assistant_prefix;
+
+const POST_BUILD_REPORTER_PROMPT: &str = r###"
+You are a reporter for Polybrain. A model was just built for a client in
+OnShape. Using the information below, write a concise summary for the client
+of what was actually built. Include:
+- What the model is and the features it is made of
+- The key dimensions that were used
+- Any assumptions that were made on the client's behalf
+- Anything that differs from the original plan, such as changes the client
+asked for
+
+Write every length in {{display_unit}}; the plan and script use inches, so
+convert them. Respond in first person in friendly english, in a short
+paragraph followed by a short bulleted list of dimensions. Do not mention
+OnPy, Python, or the script, and do not include a greeting or goodbye.
+
+The client's request was:
+```txt
+{{model_description}}
+```
+
+The plan was:
+```txt
+{{outline}}
+```
+
+The script that built the model was:
+```py
+{{script}}
+```
+
+Its console output was:
+```
+{{console_output}}
+```
+
+Errors that were fixed while building:
+{{repairs}}
+
+Changes the client asked for after seeing the model:
+{{change_requests}}
+
+An error that could not be fixed, after which the client chose to stop:
+{{unresolved_error}}
+
+The parts as measured in OnShape:
+{{geometry}}
+
+Ways the measured model differs from the plan:
+{{verification_issues}}
+"###;
+
+fn bullet_list(items: &[String]) -> String {
+    if items.is_empty() {
+        return "- None".to_owned();
+    }
+    items
+        .iter()
+        .map(|item| format!("- {}", item.trim()))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub struct PostBuildReporter<'b> {
+    openai_key: &'b String,
+    model_description: &'b String,
+    outline: &'b String,
+    progress: &'b OnPyProgress,
+    display_unit: LengthUnit,
+}
+
+impl<'b> PostBuildReporter<'b> {
+    pub fn new(
+        openai_key: &'b String,
+        model_description: &'b String,
+        outline: &'b String,
+        progress: &'b OnPyProgress,
+        display_unit: LengthUnit,
+    ) -> PostBuildReporter<'b> {
+        PostBuildReporter {
+            openai_key,
+            model_description,
+            outline,
+            progress,
+            display_unit,
+        }
+    }
+
+    pub async fn run(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let opts = options! {
+            Model: Model::Other("gpt-4o".to_string()),
+            ApiKey: self.openai_key.clone()
+        };
+        let exec = executor!(chatgpt, opts)?;
+
+        let parameters = parameters!(
+            "display_unit" => self.display_unit.symbol(),
+            "model_description" => self.model_description,
+            "outline" => self.outline,
+            "script" => &self.progress.latest_script,
+            "console_output" => &self.progress.console_output,
+            "repairs" => bullet_list(&self.progress.repairs),
+            "change_requests" => bullet_list(&self.progress.change_requests),
+            "unresolved_error" => self.progress.unresolved_error.as_deref().unwrap_or("None"),
+            "geometry" => self
+                .progress
+                .geometry
+                .as_ref()
+                .map(|geometry| geometry.to_description_in(self.display_unit))
+                .unwrap_or_else(|| "Not measured".to_owned()),
+            "verification_issues" => bullet_list(&self.progress.verification_issues),
+        );
+
+        let report = prompt!(POST_BUILD_REPORTER_PROMPT)
+            .run(&parameters, &exec)
+            .await?
+            .to_immediate()
+            .await?
+            .primary_textual_output()
+            .unwrap_or_default();
+
+        let report = trim_assistant_prefix(&report).trim().to_owned();
+        println!("Post-build report: {}", report);
+
+        Ok(report)
+    }
+}
@@ -1,14 +1,10 @@
-use std::{error::Error, pin::Pin};
-
-use futures::Future;
 use llm_chain::options;
 use llm_chain::prompt;
 use llm_chain::{executor, parameters};
 use llm_chain_openai;
 use llm_chain_openai::chatgpt::Model;
 
-use crate::server::types::ServerResponseType;
-use crate::{chain::util::trim_assistant_prefix, server::types::ServerResponse};
+use crate::chain::{session_io::SessionIo, util::trim_assistant_prefix};
 
 const PRELIMINARY_REPORTER_PROMPT: &str = r###"
 You are a reporter for Polybrain. The following outline was written by an 
@@ -38,14 +34,7 @@ impl<'b> PreliminaryReporter<'b> {
         PreliminaryReporter { openai_key, report }
     }
 
-    pub async fn run<'a, O>(&mut self, send_output: &O) -> Result<(), Box<dyn std::error::Error>>
-    where
-        O: Fn(
-                ServerResponse,
-            ) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + Send + 'a>>
-            + Send
-            + 'a,
-    {
+    pub async fn run(&mut self, io: &SessionIo<'_>) -> Result<(), Box<dyn std::error::Error>> {
         let opts = options! {
             Model: Model::Other("gpt-4o".to_string()),
             // Model: Model::Gpt35Turbo,
@@ -64,11 +53,7 @@ impl<'b> PreliminaryReporter<'b> {
         let report = trim_assistant_prefix(&report).replace("OnPy", "OnShape");
         println!("Summarized prompt as: {}", report);
 
-        send_output(ServerResponse {
-            response_type: ServerResponseType::Info,
-            content: report,
-        })
-        .await?;
+        io.notify(&report).await?;
 
         Ok(())
     }
@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::f64::consts::{E, PI};
+use std::fmt;
+
+use thiserror::Error;
+
+/// How deeply parentheses, signs and powers may nest
+const MAX_NESTING: usize = 100;
+/// The most points `polygon` and `bolt_circle` will place
+const MAX_POINTS: usize = 1000;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CalcError {
+    #[error("unexpected character '{0}'")]
+    UnexpectedCharacter(char),
+
+    #[error("unexpected end of expression")]
+    UnexpectedEnd,
+
+    #[error("unexpected {0}")]
+    UnexpectedToken(String),
+
+    #[error("unknown variable `{0}`")]
+    UnknownVariable(String),
+
+    #[error("unknown function `{0}`")]
+    UnknownFunction(String),
+
+    #[error("`{function}` takes {expected} arguments, but {found} were given")]
+    WrongArity {
+        function: String,
+        expected: &'static str,
+        found: usize,
+    },
+
+    #[error("expected a number, but got a list of points")]
+    NotANumber,
+
+    #[error("{0} is undefined")]
+    Undefined(String),
+
+    #[error("expression is nested more than {MAX_NESTING} levels deep")]
+    TooDeep,
+
+    #[error("{0} points is more than the {MAX_POINTS} allowed")]
+    TooManyPoints(String),
+}
+
+/// The result of an expression: a number or a list of 2D points
+#[derive(Debug, Clone, PartialEq)]
+pub enum CalcValue {
+    Number(f64),
+    Points(Vec<[f64; 2]>),
+}
+
+impl CalcValue {
+    fn number(self) -> Result<f64, CalcError> {
+        match self {
+            CalcValue::Number(n) => Ok(n),
+            CalcValue::Points(_) => Err(CalcError::NotANumber),
+        }
+    }
+}
+
+/// Rounds away floating point noise, so 0.30000000000000004 reads as 0.3
+fn format_number(n: f64) -> String {
+    let rounded = (n * 1e6).round() / 1e6;
+    if rounded == 0.0 {
+        "0".to_owned()
+    } else {
+        rounded.to_string()
+    }
+}
+
+impl fmt::Display for CalcValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcValue::Number(n) => write!(f, "{}", format_number(*n)),
+            CalcValue::Points(points) => {
+                let points = points
+                    .iter()
+                    .map(|[x, y]| format!("[{}, {}]", format_number(*x), format_number(*y)))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "[{points}]")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "number {n}"),
+            Token::Ident(name) => write!(f, "`{name}`"),
+            Token::Op(op) => write!(f, "'{op}'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, CalcError> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut literal = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() || c == '.' {
+                        literal.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let number = literal
+                    .parse()
+                    .map_err(|_| CalcError::UnexpectedToken(format!("number `{literal}`")))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '+' | '-' | '*' | '/' | '%' | '^' => {
+                tokens.push(Token::Op(c));
+                chars.next();
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                chars.next();
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                chars.next();
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                chars.next();
+            }
+            c => return Err(CalcError::UnexpectedCharacter(c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Evaluates arithmetic and geometry expressions without touching anything
+/// outside the calculator. Angles are in degrees.
+#[derive(Debug, Clone, Default)]
+pub struct Calculator {
+    variables: HashMap<String, CalcValue>,
+}
+
+struct Parser<'c> {
+    tokens: Vec<Token>,
+    position: usize,
+    /// How many unary expressions are being parsed, one inside the other
+    depth: usize,
+    calculator: &'c Calculator,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), CalcError> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(CalcError::UnexpectedToken(token.to_string())),
+            None => Err(CalcError::UnexpectedEnd),
+        }
+    }
+
+    fn expression(&mut self) -> Result<CalcValue, CalcError> {
+        let mut value = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek() {
+            let op = *op;
+            self.next();
+            let lhs = value.number()?;
+            let rhs = self.term()?.number()?;
+            value = operation(lhs, op, rhs, if op == '+' { lhs + rhs } else { lhs - rhs })?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<CalcValue, CalcError> {
+        let mut value = self.unary()?;
+        while let Some(Token::Op(op @ ('*' | '/' | '%'))) = self.peek() {
+            let op = *op;
+            self.next();
+            let lhs = value.number()?;
+            let rhs = self.unary()?.number()?;
+            if op != '*' && rhs == 0.0 {
+                return Err(CalcError::Undefined("division by zero".to_owned()));
+            }
+            let result = match op {
+                '*' => lhs * rhs,
+                '/' => lhs / rhs,
+                _ => lhs % rhs,
+            };
+            value = operation(lhs, op, rhs, result)?;
+        }
+        Ok(value)
+    }
+
+    /// Every nested expression passes through here, so it bounds recursion
+    fn unary(&mut self) -> Result<CalcValue, CalcError> {
+        if self.depth == MAX_NESTING {
+            return Err(CalcError::TooDeep);
+        }
+        self.depth += 1;
+        let value = match self.peek() {
+            Some(Token::Op('-')) => {
+                self.next();
+                self.unary()
+                    .and_then(CalcValue::number)
+                    .map(|n| CalcValue::Number(-n))
+            }
+            Some(Token::Op('+')) => {
+                self.next();
+                self.unary()
+            }
+            _ => self.power(),
+        };
+        self.depth -= 1;
+        value
+    }
+
+    fn power(&mut self) -> Result<CalcValue, CalcError> {
+        let base = self.atom()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.next();
+            let base = base.number()?;
+            let exponent = self.unary()?.number()?;
+            return operation(base, '^', exponent, base.powf(exponent));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<CalcValue, CalcError> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(CalcValue::Number(n)),
+            Some(Token::LParen) => {
+                let value = self.expression()?;
+                self.expect(Token::RParen)?;
+                Ok(value)
+            }
+            Some(Token::Ident(name)) => {
+                if let Some(Token::LParen) = self.peek() {
+                    self.next();
+                    let mut args = Vec::new();
+                    if let Some(Token::RParen) = self.peek() {
+                        self.next();
+                    } else {
+                        loop {
+                            args.push(self.expression()?);
+                            match self.next() {
+                                Some(Token::Comma) => continue,
+                                Some(Token::RParen) => break,
+                                Some(token) => {
+                                    return Err(CalcError::UnexpectedToken(token.to_string()))
+                                }
+                                None => return Err(CalcError::UnexpectedEnd),
+                            }
+                        }
+                    }
+                    call(&name, args)
+                } else {
+                    self.calculator.lookup(&name)
+                }
+            }
+            Some(token) => Err(CalcError::UnexpectedToken(token.to_string())),
+            None => Err(CalcError::UnexpectedEnd),
+        }
+    }
+}
+
+/// The result of `lhs op rhs`, unless it overflowed or is not a number
+fn operation(lhs: f64, op: char, rhs: f64, result: f64) -> Result<CalcValue, CalcError> {
+    if result.is_finite() {
+        Ok(CalcValue::Number(result))
+    } else {
+        Err(CalcError::Undefined(format!(
+            "{} {op} {}",
+            format_number(lhs),
+            format_number(rhs)
+        )))
+    }
+}
+
+fn numbers(
+    function: &str,
+    args: Vec<CalcValue>,
+    expected: &'static str,
+    allowed: std::ops::RangeInclusive<usize>,
+) -> Result<Vec<f64>, CalcError> {
+    if !allowed.contains(&args.len()) {
+        return Err(CalcError::WrongArity {
+            function: function.to_owned(),
+            expected,
+            found: args.len(),
+        });
+    }
+    args.into_iter().map(CalcValue::number).collect()
+}
+
+/// Places `count` points evenly around a circle, starting at `start_angle`
+fn points_on_circle(count: f64, radius: f64, start_angle: f64) -> Result<CalcValue, CalcError> {
+    if count < 1.0 || count.fract() != 0.0 {
+        return Err(CalcError::Undefined(format!(
+            "a circle of {} points",
+            format_number(count)
+        )));
+    }
+    if count > MAX_POINTS as f64 {
+        return Err(CalcError::TooManyPoints(format_number(count)));
+    }
+    let count = count as usize;
+    let points = (0..count)
+        .map(|i| {
+            let angle = (start_angle + 360.0 * i as f64 / count as f64).to_radians();
+            [radius * angle.cos(), radius * angle.sin()]
+        })
+        .collect();
+    Ok(CalcValue::Points(points))
+}
+
+fn call(function: &str, args: Vec<CalcValue>) -> Result<CalcValue, CalcError> {
+    let unary = |args: Vec<CalcValue>| numbers(function, args, "1", 1..=1).map(|a| a[0]);
+
+    let value = match function {
+        "sqrt" => {
+            let x = unary(args)?;
+            if x < 0.0 {
+                return Err(CalcError::Undefined(format!("sqrt({})", format_number(x))));
+            }
+            x.sqrt()
+        }
+        "abs" => unary(args)?.abs(),
+        "round" => unary(args)?.round(),
+        "floor" => unary(args)?.floor(),
+        "ceil" => unary(args)?.ceil(),
+        "sin" => unary(args)?.to_radians().sin(),
+        "cos" => unary(args)?.to_radians().cos(),
+        "tan" => unary(args)?.to_radians().tan(),
+        "asin" => unary(args)?.asin().to_degrees(),
+        "acos" => unary(args)?.acos().to_degrees(),
+        "atan" => unary(args)?.atan().to_degrees(),
+        "atan2" => {
+            let a = numbers(function, args, "2", 2..=2)?;
+            a[0].atan2(a[1]).to_degrees()
+        }
+        "hypot" => {
+            let a = numbers(function, args, "2", 2..=2)?;
+            a[0].hypot(a[1])
+        }
+        "dist" => {
+            let a = numbers(function, args, "4", 4..=4)?;
+            (a[2] - a[0]).hypot(a[3] - a[1])
+        }
+        "min" | "max" => {
+            let a = numbers(function, args, "at least 1", 1..=usize::MAX)?;
+            let fold = if function == "min" {
+                f64::min
+            } else {
+                f64::max
+            };
+            a.into_iter().reduce(fold).unwrap_or_default()
+        }
+        // Vertices of a regular polygon centered on the origin
+        "polygon" => {
+            let a = numbers(function, args, "2 or 3", 2..=3)?;
+            return points_on_circle(a[0], a[1], a.get(2).copied().unwrap_or(90.0));
+        }
+        // Hole centers evenly spaced on a bolt circle centered on the origin
+        "bolt_circle" => {
+            let a = numbers(function, args, "2 or 3", 2..=3)?;
+            return points_on_circle(a[0], a[1], a.get(2).copied().unwrap_or(0.0));
+        }
+        _ => return Err(CalcError::UnknownFunction(function.to_owned())),
+    };
+
+    if value.is_finite() {
+        Ok(CalcValue::Number(value))
+    } else {
+        Err(CalcError::Undefined(format!("{function}(...)")))
+    }
+}
+
+impl Calculator {
+    pub fn new() -> Self {
+        Calculator::default()
+    }
+
+    fn lookup(&self, name: &str) -> Result<CalcValue, CalcError> {
+        match name {
+            "pi" => Ok(CalcValue::Number(PI)),
+            "e" => Ok(CalcValue::Number(E)),
+            _ => self
+                .variables
+                .get(name)
+                .cloned()
+                .ok_or_else(|| CalcError::UnknownVariable(name.to_owned())),
+        }
+    }
+
+    /// Evaluates a single expression
+    pub fn evaluate(&self, expression: &str) -> Result<CalcValue, CalcError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+            depth: 0,
+            calculator: self,
+        };
+        let value = parser.expression()?;
+        match parser.next() {
+            None => Ok(value),
+            Some(token) => Err(CalcError::UnexpectedToken(token.to_string())),
+        }
+    }
+
+    /// Evaluates one line of a calculation, which is either an expression or
+    /// an assignment like `r = 2 / 2`. Assigned values can be used by later
+    /// lines.
+    pub fn evaluate_line(&mut self, line: &str) -> Result<(Option<String>, CalcValue), CalcError> {
+        if let Some((name, expression)) = line.split_once('=') {
+            let name = name.trim();
+            let is_ident = name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+            if !is_ident {
+                return Err(CalcError::UnexpectedToken(format!(
+                    "assignment to `{name}`"
+                )));
+            }
+
+            let value = self.evaluate(expression)?;
+            self.variables.insert(name.to_owned(), value.clone());
+            return Ok((Some(name.to_owned()), value));
+        }
+
+        Ok((None, self.evaluate(line)?))
+    }
+
+    /// Evaluates a multi-line calculation, skipping blank lines and `#`
+    /// comments. Every line is reported, including the ones that failed.
+    pub fn run(&mut self, calculation: &str) -> String {
+        let mut results = Vec::new();
+        for line in calculation.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            results.push(match self.evaluate_line(line) {
+                Ok((Some(name), value)) => format!("{name} = {value}"),
+                Ok((None, value)) => format!("{line} = {value}"),
+                Err(err) => format!("{line} -> ERROR: {err}"),
+            });
+        }
+        results.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(expression: &str) -> f64 {
+        match Calculator::new().evaluate(expression) {
+            Ok(CalcValue::Number(n)) => n,
+            other => panic!("{expression} evaluated to {other:?}"),
+        }
+    }
+
+    fn error(expression: &str) -> CalcError {
+        Calculator::new()
+            .evaluate(expression)
+            .expect_err(expression)
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        assert_eq!(number("1 + 2 * 3"), 7.0);
+        assert_eq!(number("(1 + 2) * 3"), 9.0);
+        assert_eq!(number("10 - 4 - 3"), 3.0);
+        assert_eq!(number("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(number("-2 ^ 2"), -4.0);
+        assert_eq!(number("7 % 4 * 2"), 6.0);
+    }
+
+    #[test]
+    fn functions_use_degrees() {
+        assert!((number("sin(30)") - 0.5).abs() < 1e-12);
+        assert!((number("atan2(1, 1)") - 45.0).abs() < 1e-12);
+        assert_eq!(number("max(1, 5, 3)"), 5.0);
+    }
+
+    #[test]
+    fn assignments_are_remembered() {
+        let mut calculator = Calculator::new();
+        assert_eq!(
+            calculator.run("r = 4 / 2\n# a comment\n\npi * r ^ 2 / pi"),
+            "r = 2\npi * r ^ 2 / pi = 4"
+        );
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        assert_eq!(error("1 +"), CalcError::UnexpectedEnd);
+        assert_eq!(error("(1 + 2"), CalcError::UnexpectedEnd);
+        assert_eq!(
+            error("1 2"),
+            CalcError::UnexpectedToken("number 2".to_owned())
+        );
+        assert_eq!(error("3 $ 4"), CalcError::UnexpectedCharacter('$'));
+        assert_eq!(
+            error("width * 2"),
+            CalcError::UnknownVariable("width".to_owned())
+        );
+        assert_eq!(
+            error("cube(2)"),
+            CalcError::UnknownFunction("cube".to_owned())
+        );
+    }
+
+    #[test]
+    fn undefined_results_are_errors() {
+        assert!(matches!(error("1 / 0"), CalcError::Undefined(_)));
+        assert!(matches!(error("sqrt(-1)"), CalcError::Undefined(_)));
+        assert!(matches!(error("10^400"), CalcError::Undefined(_)));
+        assert!(matches!(error("10^200 * 10^200"), CalcError::Undefined(_)));
+        assert!(matches!(error("10^300 / 10^-300"), CalcError::Undefined(_)));
+        assert!(matches!(error("(-8)^(1/3)"), CalcError::Undefined(_)));
+        assert_eq!(error("polygon(6, 1) + 1"), CalcError::NotANumber);
+        assert!(matches!(
+            error("hypot(3)"),
+            CalcError::WrongArity { found: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn point_counts_are_capped() {
+        match Calculator::new().evaluate("bolt_circle(4, 2)") {
+            Ok(CalcValue::Points(points)) => assert_eq!(points.len(), 4),
+            other => panic!("bolt circle evaluated to {other:?}"),
+        }
+        assert_eq!(
+            error("polygon(1000000000, 1)"),
+            CalcError::TooManyPoints("1000000000".to_owned())
+        );
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(number(&nested(MAX_NESTING - 1)), 1.0);
+        assert_eq!(error(&nested(MAX_NESTING * 100)), CalcError::TooDeep);
+        assert_eq!(error(&"-".repeat(MAX_NESTING * 100)), CalcError::TooDeep);
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::process::Command;
+
+use serde::Deserialize;
+use tokio::sync::OnceCell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityKind {
+    SketchEntity,
+    Plane,
+    Feature,
+}
+
+/// Something OnPy can do, as it appears to users, planners and the plan linter
+#[derive(Debug, Clone)]
+pub struct Capability {
+    /// The `type` tag used in the model plan
+    pub name: &'static str,
+    pub kind: CapabilityKind,
+    /// How the capability is described to the planner
+    pub description: &'static str,
+    /// How the capability is described to users
+    pub user_description: &'static str,
+    /// Plan parameters that must be present
+    pub parameters: &'static [&'static str],
+    /// The OnPy method backing the capability, as `Class.method`
+    pub onpy_method: &'static str,
+}
+
+const BUILTIN_CAPABILITIES: &[Capability] = &[
+    Capability {
+        name: "line",
+        kind: CapabilityKind::SketchEntity,
+        description: "Straight lines between two points",
+        user_description: "lines",
+        parameters: &["start", "end"],
+        onpy_method: "Sketch.add_line",
+    },
+    Capability {
+        name: "circle",
+        kind: CapabilityKind::SketchEntity,
+        description: "Circles at a specified origin",
+        user_description: "circles",
+        parameters: &["center", "radius"],
+        onpy_method: "Sketch.add_circle",
+    },
+    Capability {
+        name: "centerpoint_arc",
+        kind: CapabilityKind::SketchEntity,
+        description: "Centerpoint arcs",
+        user_description: "arcs",
+        parameters: &["center", "radius", "start_angle", "end_angle"],
+        onpy_method: "Sketch.add_centerpoint_arc",
+    },
+    Capability {
+        name: "fillet",
+        kind: CapabilityKind::SketchEntity,
+        description: "Fillets between two lines",
+        user_description: "rounded corners",
+        parameters: &["lines", "radius"],
+        onpy_method: "Sketch.add_fillet",
+    },
+    Capability {
+        name: "offset_plane",
+        kind: CapabilityKind::Plane,
+        description: "Offset Planes",
+        user_description: "Create sketches on planes offset from the top, front and right planes",
+        parameters: &[],
+        onpy_method: "PartStudio.add_offset_plane",
+    },
+    Capability {
+        name: "extrude",
+        kind: CapabilityKind::Feature,
+        description: "Extrusions",
+        user_description: "Create extrusions (addition and subtraction)",
+        parameters: &["name", "sketch", "distance"],
+        onpy_method: "PartStudio.add_extrude",
+    },
+    Capability {
+        name: "loft",
+        kind: CapabilityKind::Feature,
+        description: "Lofts",
+        user_description: "Create lofts (this is very big!)",
+        parameters: &["name", "start_sketch", "end_sketch"],
+        onpy_method: "PartStudio.add_loft",
+    },
+];
+
+/// Things users commonly ask for that OnPy cannot do
+const UNSUPPORTED: &[&str] = &[
+    "Create revolve, sweep, and chamfer features",
+    "Create complex 2D sketches",
+    "Create angled, complicated faces",
+];
+
+/// Lists the public attributes of every class in the installed onpy package
+const INTROSPECT_SCRIPT: &str = r###"
+import importlib, importlib.metadata, inspect, json, pkgutil
+import onpy
+
+classes = {}
+for module in pkgutil.walk_packages(onpy.__path__, "onpy."):
+    try:
+        module = importlib.import_module(module.name)
+    except Exception:
+        continue
+    for name, cls in inspect.getmembers(module, inspect.isclass):
+        if cls.__module__.startswith("onpy"):
+            classes.setdefault(name, set()).update(dir(cls))
+
+print(json.dumps({
+    "version": importlib.metadata.version("onpy"),
+    "classes": {name: sorted(attrs) for name, attrs in classes.items()},
+}))
+"###;
+
+#[derive(Deserialize)]
+struct OnPyIntrospection {
+    version: String,
+    classes: HashMap<String, Vec<String>>,
+}
+
+/// The set of capabilities available to this server
+#[derive(Debug, Clone)]
+pub struct CapabilityRegistry {
+    capabilities: Vec<Capability>,
+    onpy_version: Option<String>,
+}
+
+static REGISTRY: OnceCell<CapabilityRegistry> = OnceCell::const_new();
+
+/// The process-wide registry, introspected from onpy on first use
+pub async fn registry() -> &'static CapabilityRegistry {
+    REGISTRY
+        .get_or_init(|| async {
+            tokio::task::spawn_blocking(CapabilityRegistry::detect)
+                .await
+                .unwrap_or_else(|_| CapabilityRegistry::builtin())
+        })
+        .await
+}
+
+impl CapabilityRegistry {
+    /// Every capability OnPy is known to support
+    pub fn builtin() -> Self {
+        CapabilityRegistry {
+            capabilities: BUILTIN_CAPABILITIES.to_vec(),
+            onpy_version: None,
+        }
+    }
+
+    /// Narrows the builtin capabilities to those backed by methods in the
+    /// installed onpy. Falls back to the builtin set if onpy can't be inspected.
+    pub fn detect() -> Self {
+        let output = match Command::new("python")
+            .args(["-c", INTROSPECT_SCRIPT])
+            .output()
+        {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                eprintln!(
+                    "failed to introspect onpy; using builtin capabilities:\n{}",
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                return Self::builtin();
+            }
+            Err(err) => {
+                eprintln!("failed to run python; using builtin capabilities: {}", err);
+                return Self::builtin();
+            }
+        };
+
+        let introspection: OnPyIntrospection = match serde_json::from_slice(&output.stdout) {
+            Ok(introspection) => introspection,
+            Err(err) => {
+                eprintln!("malformed onpy introspection; using builtin capabilities: {err}");
+                return Self::builtin();
+            }
+        };
+
+        let capabilities = BUILTIN_CAPABILITIES
+            .iter()
+            .filter(|capability| {
+                let (class, method) = capability
+                    .onpy_method
+                    .split_once('.')
+                    .expect("onpy methods are written as Class.method");
+
+                // Only drop a capability when its class exists but lacks the method
+                let available = introspection
+                    .classes
+                    .get(class)
+                    .map_or(true, |attrs| attrs.iter().any(|attr| attr == method));
+                if !available {
+                    println!(
+                        "onpy {} does not provide {}; disabling {}",
+                        introspection.version, capability.onpy_method, capability.name
+                    );
+                }
+                available
+            })
+            .cloned()
+            .collect();
+
+        println!("detected onpy version {}", introspection.version);
+
+        CapabilityRegistry {
+            capabilities,
+            onpy_version: Some(introspection.version),
+        }
+    }
+
+    pub fn of_kind(&self, kind: CapabilityKind) -> impl Iterator<Item = &Capability> {
+        self.capabilities
+            .iter()
+            .filter(move |capability| capability.kind == kind)
+    }
+
+    /// Finds a supported capability by its plan name
+    pub fn find(&self, kind: CapabilityKind, name: &str) -> Option<&Capability> {
+        self.of_kind(kind)
+            .find(|capability| capability.name == name)
+    }
+
+    /// The plan names of every supported capability of a kind
+    pub fn names(&self, kind: CapabilityKind) -> String {
+        self.of_kind(kind)
+            .map(|capability| capability.name)
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+
+    /// Describes Polybrain's abilities to users, for the pessimist prompt
+    pub fn pessimist_section(&self) -> String {
+        let entities = self
+            .of_kind(CapabilityKind::SketchEntity)
+            .map(|capability| capability.user_description)
+            .collect::<Vec<&str>>()
+            .join(", ");
+
+        let mut section = String::from("Polybrain (a parametric modeler) has the ability to:\n");
+        section.push_str(&format!("- Create 2D sketches with primitive {entities}\n"));
+        for capability in self
+            .of_kind(CapabilityKind::Plane)
+            .chain(self.of_kind(CapabilityKind::Feature))
+        {
+            section.push_str(&format!("- {}\n", capability.user_description));
+        }
+
+        section.push_str("\nThis means that Polybrain, unlike other CAD software, is unable to:\n");
+        for unsupported in UNSUPPORTED {
+            section.push_str(&format!("- {unsupported}\n"));
+        }
+
+        section
+    }
+
+    /// Describes OnPy's constraints, for the planner prompt
+    pub fn planner_section(&self) -> String {
+        let mut section = String::from(
+            "Sketches can only be created on 2D flat planes. Within these sketches,\nusers can ONLY draw:\n",
+        );
+        for capability in self.of_kind(CapabilityKind::SketchEntity) {
+            section.push_str(&format!("- {}\n", capability.description));
+        }
+
+        section.push_str(concat!(
+            "\nAfter creating a sketch, the following features are available. If\n",
+            "a feature is not listed here, then it cannot be used in OnPy:\n",
+        ));
+        for capability in self
+            .of_kind(CapabilityKind::Feature)
+            .chain(self.of_kind(CapabilityKind::Plane))
+        {
+            section.push_str(&format!("- {}\n", capability.description));
+        }
+
+        section
+    }
+
+    /// Lists the OnPy operations the coding agent may use
+    pub fn coding_section(&self) -> String {
+        let mut section = match &self.onpy_version {
+            Some(version) => format!("The installed version of OnPy is {version}. "),
+            None => String::new(),
+        };
+        section
+            .push_str("Only the following OnPy operations are supported; do not use any others:\n");
+        for capability in &self.capabilities {
+            section.push_str(&format!(
+                "- `{}` ({})\n",
+                capability.onpy_method, capability.description
+            ));
+        }
+
+        section
+    }
+}
@@ -1,69 +1,173 @@
-use std::error::Error;
-use std::io;
-use std::{future::Future, pin::Pin};
-
-use crate::chain::agents::executive_planner::ExecutivePlanner;
-use crate::chain::agents::mathematician::MathematicianAgent;
-use crate::chain::agents::onpy_agent::OnPyAgent;
-use crate::chain::agents::pessimist::PessimistAgent;
-use crate::chain::agents::preliminary_reporter::PreliminaryReporter;
-use crate::server::types::{ApiCredentials, ServerResponse, ServerResponseType};
-
-pub async fn enter_chain<'a, I, O>(
-    initial_input: &str,
+use serde_json::{json, Value};
+
+use crate::chain::checkpoint::{Checkpointer, SessionState};
+use crate::chain::pipeline::{ChainConfig, ChainError, Pipeline, SessionContext};
+use crate::chain::session_io::SessionIo;
+use crate::server::types::ApiCredentials;
+
+/// Runs the chain for a session. A fresh session starts from a state holding
+/// only the user's prompt; a resumed one picks up from its last checkpoint.
+pub async fn enter_chain(
+    state: SessionState,
     credentials: ApiCredentials,
     onshape_document_id: String,
-    query_input: I,
-    send_output: O,
-) -> io::Result<()>
-where
-    I: Fn(String) -> Pin<Box<dyn Future<Output = Result<String, Box<dyn Error>>> + Send + 'a>>
-        + Send
-        + 'a,
-    O: Fn(ServerResponse) -> Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>> + Send + 'a>>
-        + Send
-        + 'a,
-{
-    println!("Entering chain with initial input: {}", initial_input);
-
-    // Pessimist Chain
-    let mut pessimist = PessimistAgent::new(&credentials.openai_token);
-    let parsed_prompt = pessimist
-        .run(initial_input, &query_input, &send_output)
-        .await
-        .map_err(|err| eprintln!("Pessimist errored: {}", err))
-        .unwrap();
+    config: ChainConfig,
+    io: SessionIo<'_>,
+    checkpointer: Checkpointer,
+) -> Result<(), ChainError> {
+    println!("Entering chain with initial input: {}", state.initial_input);
 
-    // Mathematician Chain
-    let mathematician = MathematicianAgent::new(&credentials.openai_token);
-    let math_notes = mathematician.run().await;
-
-    // Executive Planner Chain
-    let mut executive_planner =
-        ExecutivePlanner::new(&credentials.openai_token, &parsed_prompt, &math_notes).unwrap();
-    let modeler_outline = executive_planner.run(&query_input).await.unwrap();
-    println!("The modeler outline is:\n{}", modeler_outline);
-
-    // Preliminary Reporter Chain
-    let mut preliminary_reporter =
-        PreliminaryReporter::new(&credentials.openai_token, modeler_outline.clone());
-    preliminary_reporter.run(&send_output).await.unwrap();
-
-    // OnPy Agent Chain
-    let mut onpy_agent = OnPyAgent::new(
-        &credentials.openai_token,
-        modeler_outline,
-        parsed_prompt,
+    let mut ctx = SessionContext::new(
+        credentials,
         onshape_document_id,
+        config,
+        io.clone(),
+        checkpointer,
+        state,
     );
-    onpy_agent.run(&query_input).await.unwrap();
 
-    send_output(ServerResponse {
-        response_type: ServerResponseType::Final,
-        content: "Your model has been created!".to_owned(),
-    })
-    .await
-    .unwrap();
+    let pipeline = Pipeline::from_kinds(&ctx.config.stages);
+    pipeline.run(&mut ctx).await?;
+
+    let (closing, payload) = closing_message(&ctx.state);
+    io.finish(closing, payload)
+        .await
+        .map_err(|err| ChainError::Output(err.to_string()))?;
 
     Ok(())
 }
+
+/// The session's closing message, and a payload listing the exported files
+/// and where to download them, if there are any
+fn closing_message(state: &SessionState) -> (&str, Option<Value>) {
+    let closing = match (&state.rejection, &state.build_report) {
+        (Some(_), _) => "No model was created.",
+        (None, Some(report)) => report,
+        (None, None) => "Your model has been created!",
+    };
+    let payload = (!state.exports.is_empty()).then(|| json!({ "exports": state.exports }));
+    (closing, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::chain::export::{ExportFormat, ExportedFile};
+    use crate::chain::fake_onpy::ExecutionBackend;
+    use crate::chain::pipeline::StageKind;
+    use crate::chain::session_io::ScriptedTransport;
+    use crate::onshape::mock::MockServer;
+    use crate::server::types::ServerResponseType;
+
+    const PLAN: &str = r#"
+summary: A plate
+part_count: 1
+sketches:
+  - name: Plate
+    plane:
+      base: Top
+    entities:
+      - type: line
+        start: [0.0, 0.0]
+        end: [2.0, 1.0]
+features:
+  - type: extrude
+    name: Plate
+    sketch: Plate
+    distance: 0.5
+"#;
+
+    const SCRIPT: &str = concat!(
+        "```python\n",
+        "sketch = partstudio.add_sketch(partstudio.features.top_plane, name=\"Plate\")\n",
+        "sketch.add_corner_rectangle((0, 0), (2, 1))\n",
+        "partstudio.add_extrude(sketch.faces, 0.5, name=\"Plate\")\n",
+        "```",
+    );
+
+    #[test]
+    fn closing_payload_references_exports() {
+        let mut state = SessionState::new("A plate");
+        assert_eq!(closing_message(&state).1, None);
+
+        state.exports.push(ExportedFile {
+            format: ExportFormat::Step,
+            name: "model.step".to_owned(),
+            size: 1024,
+            external_data_id: "0123456789abcdef01234567".to_owned(),
+            url: "https://cad.onshape.com/api/v6/documents/d/abc/externaldata/0123456789abcdef01234567"
+                .to_owned(),
+        });
+        let (_, payload) = closing_message(&state);
+        let export = &payload.unwrap()["exports"][0];
+        assert_eq!(export["format"], "STEP");
+        assert_eq!(export["external_data_id"], "0123456789abcdef01234567");
+        assert!(export["url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/externaldata/0123456789abcdef01234567"));
+    }
+
+    #[tokio::test]
+    #[ignore = "drives the OpenAI executor and python against a local mock; run with --ignored"]
+    async fn sessions_build_against_mocks() {
+        let mock = MockServer::start("127.0.0.1:0", "mock-access", "mock-secret")
+            .await
+            .unwrap();
+        mock.reply_to("Use OnPy (described below)", SCRIPT);
+        mock.reply_to("Classify the response", "decision: accept");
+        mock.reply_to(
+            "You are a reporter for Polybrain",
+            "I built a 2 x 1 inch plate.",
+        );
+        std::env::set_var("ONSHAPE_API_URL", mock.api_url());
+        std::env::set_var("OPENAI_API_BASE_URL", mock.openai_url());
+        std::env::set_var("ONPY_GUIDE_URL", mock.guide_url());
+
+        let config = ChainConfig {
+            stages: vec![
+                StageKind::OnPy,
+                StageKind::Export,
+                StageKind::PostBuildReporter,
+            ],
+            execution_backend: ExecutionBackend::FakeOnpy,
+            ..ChainConfig::default()
+        };
+        let mut state = SessionState::new("A 2 x 1 inch plate, half an inch thick");
+        state.model_plan = Some(serde_yaml::from_str(PLAN).unwrap());
+        let transport = Arc::new(ScriptedTransport::new(vec!["looks good".to_owned()]));
+        let credentials = ApiCredentials {
+            openai_token: "mock-token".to_owned(),
+            onshape_access_key: "mock-access".to_owned(),
+            onshape_secret_key: "mock-secret".to_owned(),
+        };
+
+        enter_chain(
+            state,
+            credentials,
+            "0123456789abcdef01234567".to_owned(),
+            config,
+            SessionIo::new(transport.clone()),
+            Checkpointer::disabled(),
+        )
+        .await
+        .unwrap();
+
+        let transcript = transport.transcript();
+        assert!(transcript.iter().any(|message| {
+            message.response_type == ServerResponseType::Artifact
+                && message.content.contains("add_corner_rectangle")
+        }));
+        let closing = transcript.last().unwrap();
+        assert_eq!(closing.response_type, ServerResponseType::Final);
+        assert_eq!(closing.content, "I built a 2 x 1 inch plate.");
+
+        // Fake builds only ever talk to the OpenAI stand-in and the guide
+        let requested = mock.requested();
+        assert!(requested
+            .iter()
+            .all(|request| request.contains("/v1/") || request.ends_with("/onpy/guide.md")));
+    }
+}
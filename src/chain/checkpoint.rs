@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::chain::agents::pessimist::TranscriptMessage;
+use crate::chain::existing_model::ExistingModel;
+use crate::chain::export::ExportedFile;
+use crate::chain::fake_onpy::OnPyTrace;
+use crate::chain::onshape::BuildTarget;
+use crate::chain::pipeline::ChainConfig;
+use crate::chain::plan::ModelPlan;
+use crate::chain::spec::ModelSpec;
+use crate::chain::verification::BuildGeometry;
+
+/// Where the OnPy agent left off
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OnPyProgress {
+    pub iteration: usize,
+    pub scratchpad: String,
+    pub latest_script: String,
+    /// Every script that built successfully, oldest first
+    #[serde(default)]
+    pub history: Vec<BuildVersion>,
+    /// The iteration whose script is currently in the document
+    #[serde(default)]
+    pub current_iteration: Option<usize>,
+    /// Console output of the latest script
+    #[serde(default)]
+    pub console_output: String,
+    /// Errors that were repaired along the way
+    #[serde(default)]
+    pub repairs: Vec<String>,
+    /// Changes the user asked for after seeing a build
+    #[serde(default)]
+    pub change_requests: Vec<String>,
+    /// An error the user chose to stop on, rather than keep repairing
+    #[serde(default)]
+    pub unresolved_error: Option<String>,
+    /// The parts the latest build produced, as measured
+    #[serde(default)]
+    pub geometry: Option<BuildGeometry>,
+    /// Differences from the plan the latest build still has
+    #[serde(default)]
+    pub verification_issues: Vec<String>,
+    /// What the latest build traced, when it ran under the fake onpy package
+    #[serde(default)]
+    pub trace: Option<OnPyTrace>,
+}
+
+/// A script that built successfully, and the iteration that produced it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildVersion {
+    pub iteration: usize,
+    pub script: String,
+}
+
+/// Everything the chain has produced so far in a session
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SessionState {
+    pub initial_input: String,
+    /// The conversation with the pessimist so far
+    #[serde(default)]
+    pub pessimist_transcript: Vec<TranscriptMessage>,
+    pub parsed_prompt: Option<String>,
+    pub model_spec: Option<ModelSpec>,
+    pub math_notes: Option<String>,
+    pub modeler_outline: Option<String>,
+    pub model_plan: Option<ModelPlan>,
+    pub onpy: Option<OnPyProgress>,
+    /// Where OnPy builds, chosen the first time it's needed
+    pub build_target: Option<BuildTarget>,
+    /// What the part studio held before an edit-existing session changed it
+    pub existing_model: Option<ExistingModel>,
+    pub completed_stages: Vec<String>,
+    /// Why the request was turned down, if it was; no later stages run
+    pub rejection: Option<String>,
+    /// A summary of what was actually built
+    pub build_report: Option<String>,
+    /// Files the built model was exported to
+    #[serde(default)]
+    pub exports: Vec<ExportedFile>,
+}
+
+impl SessionState {
+    pub fn new(initial_input: &str) -> Self {
+        SessionState {
+            initial_input: initial_input.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A persisted snapshot of a session's state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint {
+    pub session_id: String,
+    pub user_id: String,
+    pub onshape_document_id: String,
+    /// The options the session was started with; missing in checkpoints
+    /// saved before they were recorded
+    #[serde(default)]
+    pub config: Option<ChainConfig>,
+    pub state: SessionState,
+}
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("checkpoint store failed: {0}")]
+    Store(String),
+}
+
+/// Persistent storage for session checkpoints
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    /// Creates or replaces the checkpoint for a session
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<(), CheckpointError>;
+
+    /// Loads the latest checkpoint for a session, if there is one
+    async fn load(&self, session_id: &str) -> Result<Option<Checkpoint>, CheckpointError>;
+}
+
+/// Saves the state of one session to a checkpoint store
+#[derive(Clone)]
+pub struct Checkpointer {
+    store: Option<Arc<dyn CheckpointStore>>,
+    session_id: String,
+    user_id: String,
+    onshape_document_id: String,
+    config: Option<ChainConfig>,
+}
+
+impl Checkpointer {
+    pub fn new(
+        store: Arc<dyn CheckpointStore>,
+        session_id: String,
+        user_id: String,
+        onshape_document_id: String,
+        config: ChainConfig,
+    ) -> Self {
+        Checkpointer {
+            store: Some(store),
+            session_id,
+            user_id,
+            onshape_document_id,
+            config: Some(config),
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// A checkpointer that discards everything, for sessions that can't be resumed
+    pub fn disabled() -> Self {
+        Checkpointer {
+            store: None,
+            session_id: String::new(),
+            user_id: String::new(),
+            onshape_document_id: String::new(),
+            config: None,
+        }
+    }
+
+    /// Persists the session state. Failures are logged but never interrupt
+    /// the session.
+    pub async fn save(&self, state: &SessionState) {
+        let Some(store) = &self.store else {
+            return;
+        };
+
+        let checkpoint = Checkpoint {
+            session_id: self.session_id.clone(),
+            user_id: self.user_id.clone(),
+            onshape_document_id: self.onshape_document_id.clone(),
+            config: self.config.clone(),
+            state: state.clone(),
+        };
+
+        match store.save(&checkpoint).await {
+            Ok(()) => println!("saved checkpoint for session {}", self.session_id),
+            Err(err) => eprintln!(
+                "failed to save checkpoint for session {}: {}",
+                self.session_id, err
+            ),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chain::units::LengthUnit;
+
+/// Tells an agent that the request changes a model that already exists
+const EXISTING_MODEL_NOTES: &str = "
+The user's request is a CHANGE to a model that already exists in their part
+studio. Only the change is new; keep every existing feature as it is. The
+existing features are already in the part studio when any code runs, so they
+must never be recreated.
+
+The existing model is:
+```txt
+{{existing_model}}
+```
+";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExistingFeature {
+    pub name: String,
+    pub feature_type: String,
+    pub suppressed: bool,
+}
+
+/// An axis-aligned bounding box, in inches
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+    pub low: [f64; 3],
+    pub high: [f64; 3],
+}
+
+impl BoundingBox {
+    pub fn from_meters(low: [f64; 3], high: [f64; 3]) -> BoundingBox {
+        BoundingBox {
+            low: low.map(|value| LengthUnit::Meter.to_inches(value)),
+            high: high.map(|value| LengthUnit::Meter.to_inches(value)),
+        }
+    }
+
+    /// The length of the box along x, y, and z
+    pub fn size(&self) -> [f64; 3] {
+        [0, 1, 2].map(|axis| self.high[axis] - self.low[axis])
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExistingPart {
+    pub name: String,
+    pub bounding_box: Option<BoundingBox>,
+}
+
+/// What a part studio contained before the session changed it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExistingModel {
+    pub part_studio_name: Option<String>,
+    pub features: Vec<ExistingFeature>,
+    pub parts: Vec<ExistingPart>,
+}
+
+impl ExistingModel {
+    /// Renders the model with every length in `unit`
+    pub fn to_description_in(&self, unit: LengthUnit) -> String {
+        let mut text = match &self.part_studio_name {
+            Some(name) => format!("Part studio \"{}\"\n", name),
+            None => "Part studio\n".to_owned(),
+        };
+
+        if self.features.is_empty() {
+            text.push_str("\nThe part studio has no features.\n");
+        } else {
+            text.push_str("\nFeatures, in order:\n");
+            for feature in &self.features {
+                let suppressed = if feature.suppressed {
+                    " (suppressed)"
+                } else {
+                    ""
+                };
+                text.push_str(&format!(
+                    "- {} ({}){}\n",
+                    feature.name, feature.feature_type, suppressed
+                ));
+            }
+        }
+
+        if !self.parts.is_empty() {
+            text.push_str("\nParts:\n");
+            for part in &self.parts {
+                match &part.bounding_box {
+                    Some(bounding_box) => {
+                        let [x, y, z] = bounding_box.size();
+                        let [low_x, low_y, low_z] = bounding_box.low;
+                        text.push_str(&format!(
+                            "- {}: {} x {} x {}, lowest corner at ({}, {}, {})\n",
+                            part.name,
+                            unit.format(x),
+                            unit.format(y),
+                            unit.format(z),
+                            unit.format_value(low_x),
+                            unit.format_value(low_y),
+                            unit.format_value(low_z),
+                        ));
+                    }
+                    None => text.push_str(&format!("- {}\n", part.name)),
+                }
+            }
+        }
+
+        text
+    }
+
+    /// The names of the features already in the part studio, which a plan
+    /// for a change may build on
+    pub fn feature_names(&self) -> Vec<String> {
+        self.features
+            .iter()
+            .map(|feature| feature.name.clone())
+            .collect()
+    }
+
+    /// The notes agents receive about the model, with lengths in `unit`
+    pub fn prompt_section(&self, unit: LengthUnit) -> String {
+        EXISTING_MODEL_NOTES.replace("{{existing_model}}", &self.to_description_in(unit))
+    }
+}
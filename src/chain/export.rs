@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// A file format built models can be exported to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ExportFormat {
+    Step,
+    Stl,
+    Parasolid,
+}
+
+impl ExportFormat {
+    /// The format's name in Onshape's translation API
+    pub fn format_name(self) -> &'static str {
+        match self {
+            ExportFormat::Step => "STEP",
+            ExportFormat::Stl => "STL",
+            ExportFormat::Parasolid => "PARASOLID",
+        }
+    }
+
+    /// The MIME type files in the format are sent with
+    pub fn media_type(self) -> &'static str {
+        match self {
+            ExportFormat::Step => "model/step",
+            ExportFormat::Stl => "model/stl",
+            ExportFormat::Parasolid => "application/octet-stream",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Step => "step",
+            ExportFormat::Stl => "stl",
+            ExportFormat::Parasolid => "x_t",
+        }
+    }
+}
+
+/// A file exported from a session's model. It's sent to the client once, and
+/// stays in Onshape so it can be fetched again, such as after a resume.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportedFile {
+    pub format: ExportFormat,
+    pub name: String,
+    /// In bytes
+    pub size: usize,
+    /// The file's external data id in the session's document
+    pub external_data_id: String,
+    /// Where the file can be downloaded from Onshape with the user's keys
+    pub url: String,
+}
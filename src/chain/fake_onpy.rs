@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chain::plan::ModelPlan;
+
+/// The name of the trace in the agent's per-session workdir; the agent passes
+/// its full path to the fake onpy package in `ONPY_TRACE_FILE`
+pub const TRACE_FILE: &str = "onpy_trace.json";
+
+/// Where the fake onpy package lives, put on PYTHONPATH for fake builds: the
+/// FAKE_ONPY_DIR environment variable, or `fake-onpy` in the working directory
+pub fn fake_onpy_dir() -> PathBuf {
+    let dir = std::env::var_os("FAKE_ONPY_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("fake-onpy"));
+    match std::env::current_dir() {
+        Ok(cwd) => cwd.join(dir),
+        Err(_) => dir,
+    }
+}
+
+/// Where generated OnPy scripts run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionBackend {
+    /// The real onpy package, building in the user's Onshape document
+    #[default]
+    Onshape,
+    /// A fake onpy package that only records what the script builds
+    FakeOnpy,
+}
+
+impl ExecutionBackend {
+    /// The backend named by the ONPY_BACKEND environment variable, `onshape`
+    /// or `fake_onpy`
+    pub fn from_env() -> ExecutionBackend {
+        match std::env::var("ONPY_BACKEND").as_deref() {
+            Ok("fake_onpy") | Ok("fake") => ExecutionBackend::FakeOnpy,
+            Ok("onshape") | Err(_) => ExecutionBackend::Onshape,
+            Ok(other) => {
+                eprintln!("unknown ONPY_BACKEND '{}'; building in Onshape", other);
+                ExecutionBackend::Onshape
+            }
+        }
+    }
+}
+
+/// What a script built under the fake onpy package, written as a model plan
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OnPyTrace {
+    /// Whether the script wiped the part studio before building
+    #[serde(default)]
+    pub wiped: bool,
+    pub model: ModelPlan,
+}
+
+/// Reads the trace the last fake build left at `path`. Returns `None` if
+/// there is none or it can't be read.
+pub async fn read_trace(path: &Path) -> Option<OnPyTrace> {
+    let contents = match tokio::fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(err) => {
+            eprintln!("failed to read the onpy trace: {}", err);
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&contents) {
+        Ok(trace) => Some(trace),
+        Err(err) => {
+            eprintln!("malformed onpy trace: {}", err);
+            None
+        }
+    }
+}
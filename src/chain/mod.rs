@@ -1,4 +1,19 @@
 pub mod agents;
+pub mod calculator;
+pub mod capabilities;
 pub mod chain_entry;
+pub mod checkpoint;
+pub mod existing_model;
+pub mod export;
+pub mod fake_onpy;
+pub mod onshape;
+pub mod pipeline;
+pub mod plan;
+pub mod plan_lint;
+pub mod session_io;
+pub mod spec;
+pub mod stages;
 pub mod tools;
+pub mod units;
 pub mod util;
+pub mod verification;
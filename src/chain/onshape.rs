@@ -0,0 +1,545 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chain::existing_model::{BoundingBox, ExistingFeature, ExistingModel, ExistingPart};
+use crate::chain::units::LengthUnit;
+use crate::chain::verification::{BuildGeometry, PartGeometry};
+use crate::onshape::{OnshapeClient, OnshapeError};
+use crate::server::types::{onshape_api, ApiCredentials, DEFAULT_ONSHAPE_API};
+
+/// How a session may change the part studio it builds in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum BuildMode {
+    /// Build in a new part studio, or a selected one that is empty
+    #[default]
+    Isolated,
+    /// Replace every feature in the part studio
+    Wipe,
+    /// Keep the part studio's features and add to them
+    EditExisting,
+}
+
+/// Which part studio the client asked the session to build in
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PartStudioSelection {
+    /// Defaults to the document's default workspace
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+    #[serde(default)]
+    pub element_id: Option<String>,
+    /// Picks the part studio by name when no `element_id` is given
+    #[serde(default)]
+    pub part_studio_name: Option<String>,
+}
+
+impl PartStudioSelection {
+    pub fn is_empty(&self) -> bool {
+        self.workspace_id.is_none() && self.element_id.is_none() && self.part_studio_name.is_none()
+    }
+}
+
+/// Where a session's OnPy scripts build the model
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildTarget {
+    pub document_id: String,
+    /// `None` means the document's default workspace
+    #[serde(default)]
+    pub workspace_id: Option<String>,
+    /// The part studio to build in; `None` means the workspace's first one
+    pub element_id: Option<String>,
+    #[serde(default)]
+    pub part_studio_name: Option<String>,
+    /// The version saved before the session first changed the document
+    pub snapshot_version_id: Option<String>,
+    /// When editing an existing model, the features that were there before
+    /// the session. Only features added after them are replaced on a rebuild.
+    #[serde(default)]
+    pub kept_features: Option<Vec<String>>,
+}
+
+/// Whether `id` is shaped like an Onshape document, workspace or element id:
+/// 24 hex digits
+pub fn is_onshape_id(id: &str) -> bool {
+    id.len() == 24 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Writes `value` as a Python string literal. JSON string escapes are all
+/// valid in Python.
+fn python_string(value: &str) -> String {
+    serde_json::Value::from(value).to_string()
+}
+
+impl BuildTarget {
+    /// The Python that opens the target part studio, run before every script
+    pub fn preamble(&self) -> String {
+        let mut arguments = Vec::new();
+        if let Some(workspace_id) = &self.workspace_id {
+            arguments.push(format!("wid={}", python_string(workspace_id)));
+        }
+        if let Some(element_id) = &self.element_id {
+            arguments.push(format!("element_id={}", python_string(element_id)));
+        }
+        let mut preamble = "import onpy\n".to_owned();
+        let base_url = onshape_api();
+        if base_url != DEFAULT_ONSHAPE_API {
+            preamble.push_str(&format!(
+                "onpy.api.rest_api.RestApi.BASE_URL = {}\n",
+                python_string(&base_url)
+            ));
+        }
+        preamble.push_str(&format!(
+            "partstudio = onpy.get_document({}).get_partstudio({})\n",
+            python_string(&self.document_id),
+            arguments.join(", ")
+        ));
+        preamble
+    }
+}
+
+/// Checks that the document exists and the user's keys can edit it. When the
+/// session will build in an existing part studio, there must be one in the
+/// workspace it builds in: `workspace_id`, or the default workspace.
+pub async fn check_document(
+    credentials: &ApiCredentials,
+    document_id: &str,
+    workspace_id: Option<&str>,
+    needs_part_studio: bool,
+) -> Result<(), OnshapeError> {
+    if !is_onshape_id(document_id) {
+        return Err(OnshapeError::DocumentNotFound(document_id.to_owned()));
+    }
+    let client = OnshapeClient::new(credentials);
+
+    let document = match client.document(document_id).await {
+        Ok(document) => document,
+        Err(OnshapeError::Status {
+            status: 400 | 404, ..
+        }) => return Err(OnshapeError::DocumentNotFound(document_id.to_owned())),
+        Err(OnshapeError::Status { status: 403, .. }) => {
+            return Err(OnshapeError::NoAccess(document_id.to_owned()))
+        }
+        Err(err) => return Err(err),
+    };
+
+    if !document.can_write() {
+        return Err(OnshapeError::ReadOnly(document_id.to_owned()));
+    }
+
+    let workspace_id = workspace_id.unwrap_or(&document.default_workspace.id);
+    if needs_part_studio
+        && client
+            .part_studios(document_id, workspace_id)
+            .await?
+            .is_empty()
+    {
+        return Err(OnshapeError::NoPartStudio(document_id.to_owned()));
+    }
+
+    Ok(())
+}
+
+/// Checks that the selected workspace and part studio exist, resolving a
+/// part studio name to its element id. Only wiping or editing may build in a
+/// part studio that already has features, unless the session is resuming and
+/// may have added them itself.
+pub async fn resolve_part_studio(
+    credentials: &ApiCredentials,
+    document_id: &str,
+    selection: PartStudioSelection,
+    mode: BuildMode,
+    resumed: bool,
+) -> Result<PartStudioSelection, OnshapeError> {
+    if selection.is_empty() {
+        return Ok(selection);
+    }
+
+    // The ids end up in generated Python, so nothing else is let through
+    for id in [&selection.workspace_id, &selection.element_id]
+        .into_iter()
+        .flatten()
+    {
+        if !is_onshape_id(id) {
+            return Err(OnshapeError::InvalidTarget(format!(
+                "\"{}\" is not an Onshape id",
+                id
+            )));
+        }
+    }
+
+    let client = OnshapeClient::new(credentials);
+    let workspace_id = match &selection.workspace_id {
+        Some(workspace_id) => {
+            let workspaces = client.workspaces(document_id).await?;
+            if !workspaces
+                .iter()
+                .any(|workspace| &workspace.id == workspace_id)
+            {
+                return Err(OnshapeError::InvalidTarget(format!(
+                    "Workspace {} does not exist in this document",
+                    workspace_id
+                )));
+            }
+            workspace_id.clone()
+        }
+        None => client.document(document_id).await?.default_workspace.id,
+    };
+
+    let part_studios = client.part_studios(document_id, &workspace_id).await?;
+
+    let part_studio = match (&selection.element_id, &selection.part_studio_name) {
+        (None, None) => return Ok(selection),
+        (Some(element_id), _) => part_studios
+            .into_iter()
+            .find(|element| &element.id == element_id)
+            .ok_or_else(|| {
+                OnshapeError::InvalidTarget(format!("No part studio has the id {}", element_id))
+            })?,
+        (None, Some(name)) => {
+            let mut matches = part_studios
+                .into_iter()
+                .filter(|element| element.name.eq_ignore_ascii_case(name));
+            match (matches.next(), matches.next()) {
+                (Some(element), None) => element,
+                (Some(_), Some(_)) => {
+                    return Err(OnshapeError::InvalidTarget(format!(
+                        "More than one part studio is named \"{}\"; select it by element id",
+                        name
+                    )))
+                }
+                (None, _) => {
+                    return Err(OnshapeError::InvalidTarget(format!(
+                        "No part studio is named \"{}\"",
+                        name
+                    )))
+                }
+            }
+        }
+    };
+
+    if mode == BuildMode::Isolated
+        && !resumed
+        && !client
+            .features(document_id, &workspace_id, &part_studio.id)
+            .await?
+            .is_empty()
+    {
+        return Err(OnshapeError::InvalidTarget(format!(
+            "Part studio \"{}\" already has features; allow wiping or edit it instead",
+            part_studio.name
+        )));
+    }
+
+    Ok(PartStudioSelection {
+        workspace_id: selection.workspace_id,
+        element_id: Some(part_studio.id),
+        part_studio_name: Some(part_studio.name),
+    })
+}
+
+/// Snapshots the document, then picks where the session builds: the selected
+/// part studio if there is one, otherwise a new part studio. When wiping or
+/// editing without a selection, it is the first part studio in the workspace.
+pub async fn prepare_build_target(
+    credentials: &ApiCredentials,
+    document_id: &str,
+    session_id: &str,
+    selection: &PartStudioSelection,
+    mode: BuildMode,
+) -> Result<BuildTarget, OnshapeError> {
+    let client = OnshapeClient::new(credentials);
+    let workspace_id = match &selection.workspace_id {
+        Some(workspace_id) => workspace_id.clone(),
+        None => client.document(document_id).await?.default_workspace.id,
+    };
+
+    let label = match session_id.split('-').next() {
+        Some(short_id) if !short_id.is_empty() => format!("Polybrain {}", short_id),
+        _ => "Polybrain".to_owned(),
+    };
+
+    let snapshot_version_id = client
+        .create_version(
+            document_id,
+            &workspace_id,
+            &format!("Before {}", label),
+            "Saved automatically before Polybrain changed this document",
+        )
+        .await?
+        .id;
+    println!("saved document snapshot as version {}", snapshot_version_id);
+
+    let (element_id, part_studio_name) = match (&selection.element_id, mode) {
+        (Some(element_id), _) => (Some(element_id.clone()), selection.part_studio_name.clone()),
+        (None, BuildMode::Wipe) => (None, None),
+        (None, BuildMode::EditExisting) => {
+            let first = client
+                .part_studios(document_id, &workspace_id)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| OnshapeError::NoPartStudio(document_id.to_owned()))?;
+            (Some(first.id), Some(first.name))
+        }
+        (None, BuildMode::Isolated) => {
+            let element_id = client
+                .create_part_studio(document_id, &workspace_id, &label)
+                .await?
+                .id;
+            println!("building in new part studio {}", element_id);
+            (Some(element_id), Some(label))
+        }
+    };
+
+    let kept_features = match (&element_id, mode) {
+        (Some(element_id), BuildMode::EditExisting) => Some(
+            client
+                .features(document_id, &workspace_id, element_id)
+                .await?
+                .into_iter()
+                .map(|feature| feature.feature_id)
+                .collect(),
+        ),
+        _ => None,
+    };
+
+    Ok(BuildTarget {
+        document_id: document_id.to_owned(),
+        workspace_id: selection.workspace_id.clone(),
+        element_id,
+        part_studio_name,
+        snapshot_version_id: Some(snapshot_version_id),
+        kept_features,
+    })
+}
+
+/// The workspace and element a target resolves to, for REST calls
+async fn target_ids(
+    client: &OnshapeClient,
+    target: &BuildTarget,
+) -> Result<(String, String), OnshapeError> {
+    let workspace_id = match &target.workspace_id {
+        Some(workspace_id) => workspace_id.clone(),
+        None => {
+            client
+                .document(&target.document_id)
+                .await?
+                .default_workspace
+                .id
+        }
+    };
+    let element_id = match &target.element_id {
+        Some(element_id) => element_id.clone(),
+        None => {
+            client
+                .part_studios(&target.document_id, &workspace_id)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| OnshapeError::NoPartStudio(target.document_id.clone()))?
+                .id
+        }
+    };
+    Ok((workspace_id, element_id))
+}
+
+/// Deletes every feature the session added on top of the kept features,
+/// newest first, so a script can be rebuilt on the original model
+pub async fn remove_added_features(
+    credentials: &ApiCredentials,
+    target: &BuildTarget,
+    kept_features: &[String],
+) -> Result<(), OnshapeError> {
+    let client = OnshapeClient::new(credentials);
+    let document_id = target.document_id.as_str();
+    let (workspace_id, element_id) = target_ids(&client, target).await?;
+
+    let features = client
+        .features(document_id, &workspace_id, &element_id)
+        .await?;
+    for feature in features.iter().rev() {
+        if !kept_features.contains(&feature.feature_id) {
+            client
+                .delete_feature(document_id, &workspace_id, &element_id, &feature.feature_id)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the features and parts already in the target part studio
+pub async fn introspect(
+    credentials: &ApiCredentials,
+    target: &BuildTarget,
+) -> Result<ExistingModel, OnshapeError> {
+    let client = OnshapeClient::new(credentials);
+    let document_id = target.document_id.as_str();
+    let (workspace_id, element_id) = target_ids(&client, target).await?;
+
+    let features = client
+        .features(document_id, &workspace_id, &element_id)
+        .await?
+        .into_iter()
+        .map(|feature| ExistingFeature {
+            name: feature.name,
+            feature_type: feature.feature_type,
+            suppressed: feature.suppressed,
+        })
+        .collect();
+
+    let mut parts = Vec::new();
+    for part in client
+        .parts(document_id, &workspace_id, &element_id)
+        .await?
+    {
+        let bounding_box = match client
+            .part_bounding_box(document_id, &workspace_id, &element_id, &part.part_id)
+            .await
+        {
+            Ok(info) => Some(BoundingBox::from_meters(
+                [info.low_x, info.low_y, info.low_z],
+                [info.high_x, info.high_y, info.high_z],
+            )),
+            Err(err) => {
+                eprintln!("failed to get the bounding box of {}: {}", part.name, err);
+                None
+            }
+        };
+        parts.push(ExistingPart {
+            name: part.name,
+            bounding_box,
+        });
+    }
+
+    Ok(ExistingModel {
+        part_studio_name: target.part_studio_name.clone(),
+        features,
+        parts,
+    })
+}
+
+/// Measures every part in the target part studio
+pub async fn measure_parts(
+    credentials: &ApiCredentials,
+    target: &BuildTarget,
+) -> Result<BuildGeometry, OnshapeError> {
+    let client = OnshapeClient::new(credentials);
+    let document_id = target.document_id.as_str();
+    let (workspace_id, element_id) = target_ids(&client, target).await?;
+
+    let mut parts = Vec::new();
+    for part in client
+        .parts(document_id, &workspace_id, &element_id)
+        .await?
+    {
+        let info = client
+            .part_bounding_box(document_id, &workspace_id, &element_id, &part.part_id)
+            .await?;
+        let volume = client
+            .part_volume(document_id, &workspace_id, &element_id, &part.part_id)
+            .await?;
+        let face_count = client
+            .part_face_count(document_id, &workspace_id, &element_id, &part.part_id)
+            .await?;
+
+        parts.push(PartGeometry {
+            name: part.name,
+            bounding_box: BoundingBox::from_meters(
+                [info.low_x, info.low_y, info.low_z],
+                [info.high_x, info.high_y, info.high_z],
+            ),
+            volume: volume * LengthUnit::Meter.inches_per_unit().powi(3),
+            face_count,
+        });
+    }
+
+    Ok(BuildGeometry { parts })
+}
+
+/// How often, and how many times, to check on a translation
+const TRANSLATION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const TRANSLATION_MAX_POLLS: usize = 90;
+
+/// A finished translation of the target part studio
+pub struct Translation {
+    pub contents: Vec<u8>,
+    /// The file's id in Onshape, which keeps it after the session ends
+    pub external_data_id: String,
+    /// Where the file can be downloaded from Onshape with the user's keys
+    pub url: String,
+}
+
+/// Translates the target part studio into `format_name` and downloads the
+/// file, waiting for Onshape to finish
+pub async fn translate_part_studio(
+    credentials: &ApiCredentials,
+    target: &BuildTarget,
+    format_name: &str,
+) -> Result<Translation, OnshapeError> {
+    let client = OnshapeClient::new(credentials);
+    let document_id = target.document_id.as_str();
+    let (workspace_id, element_id) = target_ids(&client, target).await?;
+
+    let translation_id = client
+        .start_translation(document_id, &workspace_id, &element_id, format_name)
+        .await?
+        .id;
+
+    for _ in 0..TRANSLATION_MAX_POLLS {
+        let status = client.translation_status(&translation_id).await?;
+        match status.request_state.as_str() {
+            "DONE" => {
+                let file_id = status
+                    .result_external_data_ids
+                    .first()
+                    .ok_or(OnshapeError::Malformed("resultExternalDataIds"))?;
+                return Ok(Translation {
+                    contents: client.download_external_data(document_id, file_id).await?,
+                    external_data_id: file_id.clone(),
+                    url: client.external_data_url(document_id, file_id)?.to_string(),
+                });
+            }
+            "FAILED" => {
+                return Err(OnshapeError::TranslationFailed(
+                    status.failure_reason.unwrap_or_default(),
+                ))
+            }
+            _ => tokio::time::sleep(TRANSLATION_POLL_INTERVAL).await,
+        }
+    }
+
+    Err(OnshapeError::TranslationFailed(format!(
+        "{} translation did not finish in time",
+        format_name
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_hex_ids_are_onshape_ids() {
+        assert!(is_onshape_id("0123456789abcdefABCDEF01"));
+        assert!(!is_onshape_id("0123456789abcdef"));
+        assert!(!is_onshape_id("0123456789abcdef0123456'"));
+        assert!(!is_onshape_id("0123456789abcdefghijklmn"));
+    }
+
+    #[test]
+    fn preamble_escapes_ids() {
+        let target = BuildTarget {
+            document_id: "d'); import os; ('".to_owned(),
+            workspace_id: None,
+            element_id: Some("e\\'\n".to_owned()),
+            part_studio_name: None,
+            snapshot_version_id: None,
+            kept_features: None,
+        };
+        let preamble = target.preamble();
+        assert!(preamble.ends_with(concat!(
+            "partstudio = onpy.get_document(\"d'); import os; ('\")",
+            ".get_partstudio(element_id=\"e\\\\'\\n\")\n"
+        )));
+    }
+}
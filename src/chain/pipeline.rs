@@ -0,0 +1,273 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::chain::checkpoint::{Checkpointer, SessionState};
+use crate::chain::export::ExportFormat;
+use crate::chain::fake_onpy::ExecutionBackend;
+use crate::chain::onshape::{BuildMode, PartStudioSelection};
+use crate::chain::session_io::SessionIo;
+use crate::chain::stages::{
+    ExecutivePlannerStage, ExportStage, IntrospectionStage, MathematicianStage, OnPyStage,
+    OutlineApprovalStage, PessimistStage, PostBuildReporterStage, PreliminaryReporterStage,
+};
+use crate::chain::units::LengthUnit;
+use crate::server::types::ApiCredentials;
+
+#[derive(Error, Debug)]
+pub enum ChainError {
+    #[error("{stage} stage failed: {message}")]
+    Stage {
+        stage: &'static str,
+        message: String,
+    },
+
+    #[error("failed to send output to the client: {0}")]
+    Output(String),
+}
+
+/// The stages that can be assembled into a pipeline
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageKind {
+    Introspection,
+    Pessimist,
+    Mathematician,
+    ExecutivePlanner,
+    OutlineApproval,
+    PreliminaryReporter,
+    OnPy,
+    Export,
+    PostBuildReporter,
+}
+
+impl StageKind {
+    /// The stages used when a session does not request a custom pipeline
+    pub fn default_pipeline() -> Vec<StageKind> {
+        vec![
+            StageKind::Pessimist,
+            StageKind::Mathematician,
+            StageKind::ExecutivePlanner,
+            StageKind::PreliminaryReporter,
+            StageKind::OnPy,
+            StageKind::Export,
+            StageKind::PostBuildReporter,
+        ]
+    }
+
+    fn build(self) -> Box<dyn ChainStage> {
+        match self {
+            StageKind::Introspection => Box::new(IntrospectionStage),
+            StageKind::Pessimist => Box::new(PessimistStage),
+            StageKind::Mathematician => Box::new(MathematicianStage),
+            StageKind::ExecutivePlanner => Box::new(ExecutivePlannerStage),
+            StageKind::OutlineApproval => Box::new(OutlineApprovalStage),
+            StageKind::PreliminaryReporter => Box::new(PreliminaryReporterStage),
+            StageKind::OnPy => Box::new(OnPyStage),
+            StageKind::Export => Box::new(ExportStage),
+            StageKind::PostBuildReporter => Box::new(PostBuildReporterStage),
+        }
+    }
+}
+
+/// How many times the pessimist may respond before it must decide
+pub const DEFAULT_PESSIMIST_MAX_TURNS: usize = 8;
+
+/// Per-session options that control how the chain is assembled and run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainConfig {
+    pub stages: Vec<StageKind>,
+    /// The unit lengths are shown to the user in. OnPy always receives inches.
+    pub display_unit: LengthUnit,
+    pub pessimist_max_turns: usize,
+    pub build_mode: BuildMode,
+    /// The part studio to build in; empty for a new one
+    pub part_studio: PartStudioSelection,
+    /// Formats the built model is exported to; none unless the client asks
+    pub export_formats: Vec<ExportFormat>,
+    /// Where generated OnPy scripts run
+    pub execution_backend: ExecutionBackend,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            stages: StageKind::default_pipeline(),
+            display_unit: LengthUnit::default(),
+            pessimist_max_turns: DEFAULT_PESSIMIST_MAX_TURNS,
+            build_mode: BuildMode::default(),
+            part_studio: PartStudioSelection::default(),
+            export_formats: Vec::new(),
+            execution_backend: ExecutionBackend::from_env(),
+        }
+    }
+}
+
+impl ChainConfig {
+    /// Has the user approve the executive outline before modeling, if the
+    /// pipeline doesn't already
+    pub fn add_outline_approval(&mut self) {
+        if self.stages.contains(&StageKind::OutlineApproval) {
+            return;
+        }
+        let position = self
+            .stages
+            .iter()
+            .position(|stage| *stage == StageKind::ExecutivePlanner)
+            .map_or(0, |index| index + 1);
+        self.stages.insert(position, StageKind::OutlineApproval);
+    }
+
+    /// Edits the model already in the part studio instead of building a new one
+    pub fn edit_existing(&mut self) {
+        self.build_mode = BuildMode::EditExisting;
+        if !self.stages.contains(&StageKind::Introspection) {
+            self.stages.insert(0, StageKind::Introspection);
+        }
+    }
+}
+
+/// State shared between every stage of a session
+pub struct SessionContext<'a> {
+    pub credentials: ApiCredentials,
+    pub onshape_document_id: String,
+    pub config: ChainConfig,
+    pub io: SessionIo<'a>,
+    pub checkpointer: Checkpointer,
+    pub state: SessionState,
+}
+
+impl<'a> SessionContext<'a> {
+    pub fn new(
+        credentials: ApiCredentials,
+        onshape_document_id: String,
+        config: ChainConfig,
+        io: SessionIo<'a>,
+        checkpointer: Checkpointer,
+        state: SessionState,
+    ) -> SessionContext<'a> {
+        SessionContext {
+            credentials,
+            onshape_document_id,
+            config,
+            io,
+            checkpointer,
+            state,
+        }
+    }
+
+    /// The description of the model; falls back to the raw user input when
+    /// the pessimist was skipped
+    pub fn model_description(&self) -> &String {
+        self.state
+            .parsed_prompt
+            .as_ref()
+            .unwrap_or(&self.state.initial_input)
+    }
+
+    /// The mathematician's notes, or a placeholder if none were produced
+    pub fn math_notes(&self) -> String {
+        self.state
+            .math_notes
+            .clone()
+            .unwrap_or_else(|| "No math notes".to_owned())
+    }
+
+    /// The executive outline; falls back to the model description when the
+    /// planner was skipped
+    pub fn modeler_outline(&self) -> &String {
+        self.state
+            .modeler_outline
+            .as_ref()
+            .unwrap_or_else(|| self.model_description())
+    }
+
+    /// Notes on the model being edited, for agent prompts; empty when the
+    /// session builds a new model
+    pub fn existing_model_section(&self, unit: LengthUnit) -> String {
+        self.state
+            .existing_model
+            .as_ref()
+            .map(|model| model.prompt_section(unit))
+            .unwrap_or_default()
+    }
+
+    /// The features a plan may build on; empty when the session builds a new
+    /// model
+    pub fn existing_feature_names(&self) -> Vec<String> {
+        self.state
+            .existing_model
+            .as_ref()
+            .map(|model| model.feature_names())
+            .unwrap_or_default()
+    }
+
+    /// The executive outline in the session's display unit, for the user
+    pub fn display_outline(&self) -> String {
+        match &self.state.model_plan {
+            Some(plan) => plan.to_outline_in(self.config.display_unit),
+            None => self.modeler_outline().clone(),
+        }
+    }
+}
+
+/// A single step in the LLM chain
+#[async_trait]
+pub trait ChainStage: Send + Sync {
+    /// A short name used in logs and errors
+    fn name(&self) -> &'static str;
+
+    /// Runs the stage, reading from and writing to the session context
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>>;
+}
+
+/// An ordered list of stages
+pub struct Pipeline {
+    stages: Vec<Box<dyn ChainStage>>,
+}
+
+impl Pipeline {
+    /// Builds a pipeline from a list of stage kinds
+    pub fn from_kinds(kinds: &[StageKind]) -> Self {
+        Pipeline {
+            stages: kinds.iter().map(|kind| kind.build()).collect(),
+        }
+    }
+
+    /// Runs every stage in order, stopping at the first failure or once the
+    /// request is rejected. Stages that a resumed session already completed
+    /// are skipped, matched by position so a stage can appear more than once.
+    pub async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), ChainError> {
+        for (index, stage) in self.stages.iter().enumerate() {
+            if let Some(reason) = &ctx.state.rejection {
+                println!("request was rejected, stopping: {}", reason);
+                break;
+            }
+
+            if ctx.state.completed_stages.get(index).map(String::as_str) == Some(stage.name()) {
+                println!("skipping completed {} stage", stage.name());
+                continue;
+            }
+
+            println!("entering {} stage", stage.name());
+            ctx.io
+                .progress(stage.name(), index + 1, self.stages.len())
+                .await
+                .map_err(|err| ChainError::Output(err.to_string()))?;
+
+            if let Err(err) = stage.run(ctx).await {
+                eprintln!("{} errored: {}", stage.name(), err);
+                return Err(ChainError::Stage {
+                    stage: stage.name(),
+                    message: err.to_string(),
+                });
+            }
+
+            ctx.state.completed_stages.push(stage.name().to_owned());
+            ctx.checkpointer.save(&ctx.state).await;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chain::units::LengthUnit;
+
+/// Describes the plan schema to the LLM. Must be kept in sync with [`ModelPlan`].
+pub const PLAN_FORMAT: &str = r###"
+The plan is a YAML mapping. All lengths are in Inches and all angles are in
+degrees. Sketch coordinates are (x, y) pairs on the sketch plane.
+
+summary: |
+  A short description of the model and how it will be built
+part_count: 1                   # how many separate parts the finished model is;
+                                # leave out if features may merge into each other
+sketches:
+  - name: Base Sketch           # unique name, referenced by features
+    plane:
+      base: Top                 # one of Top, Front, Right
+      offset: 0.0               # distance from the base plane
+    entities:
+      - type: line
+        start: [0.0, 0.0]
+        end: [4.0, 0.0]
+      - type: line
+        start: [4.0, 0.0]
+        end: [4.0, 3.0]
+      - type: circle
+        center: [2.0, 2.0]
+        radius: 0.5
+      - type: centerpoint_arc
+        center: [0.0, 0.0]
+        radius: 1.0
+        start_angle: 0.0
+        end_angle: 90.0
+      - type: fillet
+        lines: [0, 1]           # entity indices of two lines in this sketch
+        radius: 0.25
+features:                       # applied in order
+  - type: extrude
+    name: Base
+    sketch: Base Sketch
+    distance: 0.5
+    operation: new              # one of new, add, subtract
+    target: null                # the feature to add to or subtract from
+  - type: loft
+    name: Taper
+    start_sketch: Base Sketch
+    end_sketch: Top Sketch
+"###;
+
+/// A typed, ordered description of how to build a model
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelPlan {
+    pub summary: String,
+    /// How many separate parts the finished model is, if the plan says
+    #[serde(default)]
+    pub part_count: Option<usize>,
+    pub sketches: Vec<PlannedSketch>,
+    pub features: Vec<PlannedFeature>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BasePlane {
+    Top,
+    Front,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SketchPlane {
+    pub base: BasePlane,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedSketch {
+    pub name: String,
+    pub plane: SketchPlane,
+    pub entities: Vec<SketchEntity>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SketchEntity {
+    Line {
+        start: [f64; 2],
+        end: [f64; 2],
+    },
+    Circle {
+        center: [f64; 2],
+        radius: f64,
+    },
+    CenterpointArc {
+        center: [f64; 2],
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+    },
+    Fillet {
+        lines: [usize; 2],
+        radius: f64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtrudeOperation {
+    #[default]
+    New,
+    Add,
+    Subtract,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlannedFeature {
+    Extrude {
+        name: String,
+        sketch: String,
+        distance: f64,
+        #[serde(default)]
+        operation: ExtrudeOperation,
+        #[serde(default)]
+        target: Option<String>,
+    },
+    Loft {
+        name: String,
+        start_sketch: String,
+        end_sketch: String,
+    },
+}
+
+fn point(p: &[f64; 2], unit: LengthUnit) -> String {
+    format!("({}, {})", unit.format_value(p[0]), unit.format_value(p[1]))
+}
+
+impl SketchEntity {
+    fn describe(&self, unit: LengthUnit) -> String {
+        match self {
+            SketchEntity::Line { start, end } => {
+                format!("Line from {} to {}", point(start, unit), point(end, unit))
+            }
+            SketchEntity::Circle { center, radius } => format!(
+                "Circle at {} with radius {}",
+                point(center, unit),
+                unit.format(*radius)
+            ),
+            SketchEntity::CenterpointArc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+            } => format!(
+                "Centerpoint arc at {} with radius {} from {}° to {}°",
+                point(center, unit),
+                unit.format(*radius),
+                start_angle,
+                end_angle
+            ),
+            SketchEntity::Fillet { lines, radius } => format!(
+                "Fillet between lines {} and {} with radius {}",
+                lines[0],
+                lines[1],
+                unit.format(*radius)
+            ),
+        }
+    }
+}
+
+impl PlannedFeature {
+    fn describe(&self, unit: LengthUnit) -> String {
+        match self {
+            PlannedFeature::Extrude {
+                name,
+                sketch,
+                distance,
+                operation,
+                target,
+            } => {
+                let operation = match (operation, target) {
+                    (ExtrudeOperation::New, _) => "as a new part".to_owned(),
+                    (ExtrudeOperation::Add, Some(target)) => format!("adding to \"{target}\""),
+                    (ExtrudeOperation::Subtract, Some(target)) => {
+                        format!("subtracting from \"{target}\"")
+                    }
+                    (ExtrudeOperation::Add, None) => "adding material".to_owned(),
+                    (ExtrudeOperation::Subtract, None) => "removing material".to_owned(),
+                };
+                format!(
+                    "Extrude \"{name}\": extrude sketch \"{sketch}\" by {}, {operation}",
+                    unit.format(*distance)
+                )
+            }
+            PlannedFeature::Loft {
+                name,
+                start_sketch,
+                end_sketch,
+            } => format!(
+                "Loft \"{name}\": loft from sketch \"{start_sketch}\" to sketch \"{end_sketch}\""
+            ),
+        }
+    }
+}
+
+impl ModelPlan {
+    /// Renders the plan as step-by-step instructions for the OnPy agent
+    pub fn to_outline(&self) -> String {
+        self.to_outline_in(LengthUnit::Inch)
+    }
+
+    /// Renders the plan with every length in `unit`, for showing to users
+    pub fn to_outline_in(&self, unit: LengthUnit) -> String {
+        let mut outline = format!("{}\n", self.summary.trim());
+        outline.push_str(&format!("\nAll lengths are in {}.\n", unit));
+        if let Some(part_count) = self.part_count {
+            outline.push_str(&format!("The finished model is {part_count} part(s).\n"));
+        }
+        let mut step = 1;
+
+        for sketch in &self.sketches {
+            outline.push_str(&format!(
+                "\n{step}. Create sketch \"{}\" on the {:?} plane, offset {}:\n",
+                sketch.name,
+                sketch.plane.base,
+                unit.format(sketch.plane.offset)
+            ));
+            for (index, entity) in sketch.entities.iter().enumerate() {
+                outline.push_str(&format!("   {index}: {}\n", entity.describe(unit)));
+            }
+            step += 1;
+        }
+
+        for feature in &self.features {
+            outline.push_str(&format!("\n{step}. {}\n", feature.describe(unit)));
+            step += 1;
+        }
+
+        outline
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+
+use serde_yaml::Value;
+use thiserror::Error;
+
+use crate::chain::capabilities::{CapabilityKind, CapabilityRegistry};
+use crate::chain::plan::{ExtrudeOperation, ModelPlan, PlannedFeature, SketchEntity};
+
+/// A single problem found in a plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintViolation {
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for LintViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("{}", .0.iter().map(|v| format!("- {v}")).collect::<Vec<String>>().join("\n"))]
+pub struct LintFailure(pub Vec<LintViolation>);
+
+fn violation(location: impl Into<String>, message: impl Into<String>) -> LintViolation {
+    LintViolation {
+        location: location.into(),
+        message: message.into(),
+    }
+}
+
+/// Checks a list of tagged items (entities or features) against the supported
+/// capabilities, flagging unknown types and missing or empty parameters
+fn lint_items(
+    items: Option<&Value>,
+    location: &str,
+    capabilities: &CapabilityRegistry,
+    capability_kind: CapabilityKind,
+    kind: &str,
+    violations: &mut Vec<LintViolation>,
+) {
+    let Some(Value::Sequence(items)) = items else {
+        violations.push(violation(location, format!("expected a list of {kind}s")));
+        return;
+    };
+
+    for (index, item) in items.iter().enumerate() {
+        let item_location = format!("{location}[{index}]");
+        let Some(item_type) = item.get("type").and_then(Value::as_str) else {
+            violations.push(violation(item_location, format!("{kind} has no type")));
+            continue;
+        };
+
+        match capabilities.find(capability_kind, item_type) {
+            Some(capability) => {
+                for parameter in capability.parameters {
+                    if matches!(item.get(parameter), None | Some(Value::Null)) {
+                        violations.push(violation(
+                            &item_location,
+                            format!("{item_type} is missing its `{parameter}`"),
+                        ));
+                    }
+                }
+            }
+            None => violations.push(violation(
+                item_location,
+                format!(
+                    "`{item_type}` is not a {kind} OnPy supports. Use only: {}",
+                    capabilities.names(capability_kind)
+                ),
+            )),
+        }
+    }
+}
+
+/// Lints the raw plan before it is deserialized, so that unsupported
+/// operations and missing dimensions get a specific explanation rather than
+/// a generic parse error
+pub fn lint_plan_value(plan: &Value, capabilities: &CapabilityRegistry) -> Vec<LintViolation> {
+    let mut violations = Vec::new();
+
+    match plan.get("sketches") {
+        Some(Value::Sequence(sketches)) => {
+            for (index, sketch) in sketches.iter().enumerate() {
+                lint_items(
+                    sketch.get("entities"),
+                    &format!("sketches[{index}].entities"),
+                    capabilities,
+                    CapabilityKind::SketchEntity,
+                    "sketch entity",
+                    &mut violations,
+                );
+
+                let offset = sketch
+                    .get("plane")
+                    .and_then(|plane| plane.get("offset"))
+                    .and_then(Value::as_f64)
+                    .unwrap_or_default();
+                if offset != 0.0
+                    && capabilities
+                        .find(CapabilityKind::Plane, "offset_plane")
+                        .is_none()
+                {
+                    violations.push(violation(
+                        format!("sketches[{index}].plane"),
+                        "OnPy does not support offset planes; the offset must be 0",
+                    ));
+                }
+            }
+        }
+        _ => violations.push(violation("sketches", "expected a list of sketches")),
+    }
+
+    lint_items(
+        plan.get("features"),
+        "features",
+        capabilities,
+        CapabilityKind::Feature,
+        "feature",
+        &mut violations,
+    );
+
+    violations
+}
+
+/// Lints a deserialized plan for dimensions and references that OnPy can't
+/// build. Features may target `existing_features`, the features already in
+/// the part studio being edited.
+pub fn lint_plan(plan: &ModelPlan, existing_features: &[String]) -> Vec<LintViolation> {
+    let mut violations = Vec::new();
+    let mut sketch_names = HashSet::new();
+
+    for (index, sketch) in plan.sketches.iter().enumerate() {
+        let location = format!("sketches[{index}] ({})", sketch.name);
+
+        if !sketch_names.insert(sketch.name.as_str()) {
+            violations.push(violation(&location, "sketch names must be unique"));
+        }
+        if sketch.entities.is_empty() {
+            violations.push(violation(&location, "sketch has no entities"));
+        }
+
+        for (entity_index, entity) in sketch.entities.iter().enumerate() {
+            let entity_location = format!("{location}.entities[{entity_index}]");
+            match entity {
+                SketchEntity::Line { start, end } => {
+                    if start == end {
+                        violations.push(violation(entity_location, "line has zero length"));
+                    }
+                }
+                SketchEntity::Circle { radius, .. }
+                | SketchEntity::CenterpointArc { radius, .. } => {
+                    if *radius <= 0.0 {
+                        violations.push(violation(entity_location, "radius must be positive"));
+                    }
+                }
+                SketchEntity::Fillet { lines, radius } => {
+                    if *radius <= 0.0 {
+                        violations.push(violation(&entity_location, "radius must be positive"));
+                    }
+                    for line in lines {
+                        if !matches!(sketch.entities.get(*line), Some(SketchEntity::Line { .. })) {
+                            violations.push(violation(
+                                &entity_location,
+                                format!("entity {line} is not a line in this sketch"),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut feature_names: HashSet<&str> = existing_features.iter().map(String::as_str).collect();
+    for (index, feature) in plan.features.iter().enumerate() {
+        let location = format!("features[{index}]");
+        match feature {
+            PlannedFeature::Extrude {
+                name,
+                sketch,
+                distance,
+                operation,
+                target,
+            } => {
+                if !sketch_names.contains(sketch.as_str()) {
+                    violations.push(violation(
+                        &location,
+                        format!("sketch \"{sketch}\" does not exist"),
+                    ));
+                }
+                if *distance <= 0.0 {
+                    violations.push(violation(&location, "distance must be positive"));
+                }
+                if let (ExtrudeOperation::Add | ExtrudeOperation::Subtract, Some(target)) =
+                    (operation, target)
+                {
+                    if !feature_names.contains(target.as_str()) {
+                        violations.push(violation(
+                            &location,
+                            format!("target \"{target}\" is not an earlier feature"),
+                        ));
+                    }
+                }
+                if *operation == ExtrudeOperation::Subtract && feature_names.is_empty() {
+                    violations.push(violation(
+                        &location,
+                        "there is nothing to subtract from yet",
+                    ));
+                }
+                feature_names.insert(name);
+            }
+            PlannedFeature::Loft {
+                name,
+                start_sketch,
+                end_sketch,
+            } => {
+                for sketch in [start_sketch, end_sketch] {
+                    if !sketch_names.contains(sketch.as_str()) {
+                        violations.push(violation(
+                            &location,
+                            format!("sketch \"{sketch}\" does not exist"),
+                        ));
+                    }
+                }
+                if start_sketch == end_sketch {
+                    violations.push(violation(&location, "a loft needs two different sketches"));
+                }
+                let planes = [start_sketch, end_sketch].map(|name| {
+                    plan.sketches
+                        .iter()
+                        .find(|sketch| &sketch.name == name)
+                        .map(|sketch| &sketch.plane)
+                });
+                if let [Some(start), Some(end)] = planes {
+                    if start.base != end.base || start.offset == end.offset {
+                        violations.push(violation(
+                            &location,
+                            "loft sketches must be on parallel planes offset from each other",
+                        ));
+                    }
+                }
+                feature_names.insert(name);
+            }
+        }
+    }
+
+    violations
+}
+
+/// Deserializes a plan and lints it, failing with every violation found
+pub fn validate_plan(
+    plan: Value,
+    capabilities: &CapabilityRegistry,
+    existing_features: &[String],
+) -> Result<ModelPlan, Box<dyn Error>> {
+    let violations = lint_plan_value(&plan, capabilities);
+    if !violations.is_empty() {
+        return Err(Box::new(LintFailure(violations)));
+    }
+
+    let plan: ModelPlan = serde_yaml::from_value(plan)?;
+
+    let violations = lint_plan(&plan, existing_features);
+    if !violations.is_empty() {
+        return Err(Box::new(LintFailure(violations)));
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = r#"
+summary: A plate with a hole
+sketches:
+  - name: Base Sketch
+    plane:
+      base: Top
+    entities:
+      - type: line
+        start: [0.0, 0.0]
+        end: [4.0, 0.0]
+      - type: line
+        start: [4.0, 0.0]
+        end: [4.0, 3.0]
+      - type: fillet
+        lines: [0, 1]
+        radius: 0.25
+  - name: Hole Sketch
+    plane:
+      base: Top
+    entities:
+      - type: circle
+        center: [2.0, 1.5]
+        radius: 0.5
+features:
+  - type: extrude
+    name: Plate
+    sketch: Base Sketch
+    distance: 0.5
+  - type: extrude
+    name: Hole
+    sketch: Hole Sketch
+    distance: 0.5
+    operation: subtract
+    target: Plate
+"#;
+
+    fn plan_value() -> Value {
+        serde_yaml::from_str(PLAN).unwrap()
+    }
+
+    fn messages(violations: &[LintViolation]) -> Vec<&str> {
+        violations
+            .iter()
+            .map(|violation| violation.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn valid_plan_passes() {
+        let plan = validate_plan(plan_value(), &CapabilityRegistry::builtin(), &[]).unwrap();
+        assert_eq!(plan.features.len(), 2);
+    }
+
+    #[test]
+    fn unsupported_types_and_missing_parameters_are_flagged() {
+        let mut plan = plan_value();
+        plan["features"][0]["type"] = Value::from("revolve");
+        plan["sketches"][1]["entities"][0]
+            .as_mapping_mut()
+            .unwrap()
+            .remove("radius");
+
+        let violations = lint_plan_value(&plan, &CapabilityRegistry::builtin());
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].location, "sketches[1].entities[0]");
+        assert_eq!(violations[0].message, "circle is missing its `radius`");
+        assert_eq!(violations[1].location, "features[0]");
+        assert!(violations[1]
+            .message
+            .starts_with("`revolve` is not a feature"));
+    }
+
+    #[test]
+    fn missing_sketch_list_is_flagged() {
+        let plan: Value = serde_yaml::from_str("summary: empty\nfeatures: []").unwrap();
+        let violations = lint_plan_value(&plan, &CapabilityRegistry::builtin());
+        assert_eq!(messages(&violations), ["expected a list of sketches"]);
+    }
+
+    #[test]
+    fn bad_dimensions_and_references_are_flagged() {
+        let mut plan: ModelPlan = serde_yaml::from_value(plan_value()).unwrap();
+        plan.sketches[1].name = "Base Sketch".to_owned();
+        if let SketchEntity::Circle { radius, .. } = &mut plan.sketches[1].entities[0] {
+            *radius = 0.0;
+        }
+        if let SketchEntity::Fillet { lines, .. } = &mut plan.sketches[0].entities[2] {
+            *lines = [0, 2];
+        }
+        if let PlannedFeature::Extrude {
+            distance, target, ..
+        } = &mut plan.features[1]
+        {
+            *distance = -1.0;
+            *target = Some("Missing".to_owned());
+        }
+
+        let violations = lint_plan(&plan, &[]);
+        assert_eq!(
+            messages(&violations),
+            [
+                "entity 2 is not a line in this sketch",
+                "sketch names must be unique",
+                "radius must be positive",
+                "sketch \"Hole Sketch\" does not exist",
+                "distance must be positive",
+                "target \"Missing\" is not an earlier feature",
+            ]
+        );
+    }
+
+    #[test]
+    fn subtracting_before_any_part_is_flagged() {
+        let mut plan: ModelPlan = serde_yaml::from_value(plan_value()).unwrap();
+        plan.features.remove(0);
+        if let PlannedFeature::Extrude { target, .. } = &mut plan.features[0] {
+            *target = None;
+        }
+
+        let violations = lint_plan(&plan, &[]);
+        assert_eq!(
+            messages(&violations),
+            ["there is nothing to subtract from yet"]
+        );
+    }
+
+    #[test]
+    fn lofts_need_offset_parallel_sketches() {
+        let mut plan: ModelPlan = serde_yaml::from_value(plan_value()).unwrap();
+        plan.features.push(PlannedFeature::Loft {
+            name: "Taper".to_owned(),
+            start_sketch: "Base Sketch".to_owned(),
+            end_sketch: "Hole Sketch".to_owned(),
+        });
+
+        let violations = lint_plan(&plan, &[]);
+        assert_eq!(
+            messages(&violations),
+            ["loft sketches must be on parallel planes offset from each other"]
+        );
+
+        plan.sketches[1].plane.offset = 2.0;
+        assert!(lint_plan(&plan, &[]).is_empty());
+    }
+
+    #[test]
+    fn edits_can_cut_into_existing_features() {
+        let mut plan: ModelPlan = serde_yaml::from_value(plan_value()).unwrap();
+        plan.sketches.remove(0);
+        plan.features.remove(0);
+
+        assert_eq!(
+            messages(&lint_plan(&plan, &[])),
+            [
+                "target \"Plate\" is not an earlier feature",
+                "there is nothing to subtract from yet",
+            ]
+        );
+        assert!(lint_plan(&plan, &["Plate".to_owned()]).is_empty());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::json;
+
+use crate::server::types::{ServerResponse, ServerResponseType, UserInputResponse};
+
+/// A channel that carries messages between a session and its user
+#[async_trait]
+pub trait SessionTransport: Send + Sync {
+    /// Sends a query to the user and waits for their reply
+    async fn ask(&self, query: ServerResponse) -> Result<UserInputResponse, Box<dyn Error>>;
+
+    /// Sends a message that does not expect a reply
+    async fn send(&self, message: ServerResponse) -> Result<(), Box<dyn Error>>;
+}
+
+/// A cloneable handle that lets any stage talk to the user
+#[derive(Clone)]
+pub struct SessionIo<'a> {
+    transport: Arc<dyn SessionTransport + 'a>,
+}
+
+impl<'a> SessionIo<'a> {
+    pub fn new(transport: Arc<dyn SessionTransport + 'a>) -> Self {
+        SessionIo { transport }
+    }
+
+    /// Asks the user a question and returns their answer
+    pub async fn ask(&self, question: &str) -> Result<String, Box<dyn Error>> {
+        let reply = self
+            .transport
+            .ask(ServerResponse::new(ServerResponseType::Query, question))
+            .await?;
+        Ok(reply.response)
+    }
+
+    /// Sends a request for the user to review something and returns their
+    /// full reply, including any structured action
+    pub async fn review(
+        &self,
+        request: ServerResponse,
+    ) -> Result<UserInputResponse, Box<dyn Error>> {
+        self.transport.ask(request).await
+    }
+
+    /// Sends an informational message
+    pub async fn notify(&self, message: &str) -> Result<(), Box<dyn Error>> {
+        self.send(ServerResponse::new(ServerResponseType::Info, message))
+            .await
+    }
+
+    /// Sends a chunk of incremental output, such as console logs
+    pub async fn stream(&self, chunk: &str) -> Result<(), Box<dyn Error>> {
+        self.send(ServerResponse::new(ServerResponseType::Stream, chunk))
+            .await
+    }
+
+    /// Reports how far along the session is
+    pub async fn progress(
+        &self,
+        stage: &str,
+        step: usize,
+        total: usize,
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(
+            ServerResponse::new(
+                ServerResponseType::Progress,
+                format!("{stage} ({step}/{total})"),
+            )
+            .with_payload(json!({ "stage": stage, "step": step, "total": total })),
+        )
+        .await
+    }
+
+    /// Sends a named file produced by the session
+    pub async fn artifact(&self, name: &str, contents: &str) -> Result<(), Box<dyn Error>> {
+        self.send(
+            ServerResponse::new(ServerResponseType::Artifact, contents)
+                .with_payload(json!({ "name": name })),
+        )
+        .await
+    }
+
+    /// Sends a binary file produced by the session, base64 encoded
+    pub async fn file_artifact(
+        &self,
+        name: &str,
+        media_type: &str,
+        contents: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        self.send(
+            ServerResponse::new(
+                ServerResponseType::Artifact,
+                BASE64_STANDARD.encode(contents),
+            )
+            .with_payload(json!({
+                "name": name,
+                "media_type": media_type,
+                "encoding": "base64",
+            })),
+        )
+        .await
+    }
+
+    /// Sends the closing message of the session, with any structured results
+    pub async fn finish(
+        &self,
+        message: &str,
+        payload: Option<serde_json::Value>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut closing = ServerResponse::new(ServerResponseType::Final, message);
+        if let Some(payload) = payload {
+            closing = closing.with_payload(payload);
+        }
+        self.send(closing).await
+    }
+
+    pub async fn send(&self, message: ServerResponse) -> Result<(), Box<dyn Error>> {
+        self.transport.send(message).await
+    }
+}
+
+/// Answers queries from a fixed script and records everything sent to the
+/// user, so a session can be driven without a client
+pub struct ScriptedTransport {
+    answers: Mutex<VecDeque<String>>,
+    transcript: Mutex<Vec<ServerResponse>>,
+}
+
+impl ScriptedTransport {
+    pub fn new(answers: Vec<String>) -> Self {
+        ScriptedTransport {
+            answers: Mutex::new(answers.into()),
+            transcript: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Every message the session has sent so far, queries included
+    pub fn transcript(&self) -> Vec<ServerResponse> {
+        self.transcript.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl SessionTransport for ScriptedTransport {
+    async fn ask(&self, query: ServerResponse) -> Result<UserInputResponse, Box<dyn Error>> {
+        self.transcript.lock().unwrap().push(query);
+
+        let response = self
+            .answers
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or("Scripted session ran out of answers")?;
+        Ok(UserInputResponse {
+            response,
+            action: None,
+        })
+    }
+
+    async fn send(&self, message: ServerResponse) -> Result<(), Box<dyn Error>> {
+        self.transcript.lock().unwrap().push(message);
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chain::units::{Dimension, LengthUnit};
+
+/// Describes the spec schema to the LLM. Must be kept in sync with [`WrittenSpec`].
+pub const SPEC_FORMAT: &str = r###"
+object_type: Coffee mug         # what the object is, in a few words
+description: |
+  A short description of the model the user wants
+dimensions:                     # every size the user gave, with its unit
+  - name: Outer diameter
+    value: 80 mm
+  - name: Wall thickness
+    value: 1/8"
+features:                       # the parts of the model, one per line
+  - Cylindrical body, open at the top
+  - Solid base
+assumptions:                    # anything you decided that the user did not say
+  - The handle is not modeled
+open_questions:                 # anything still unknown
+  - How tall should the mug be?
+"###;
+
+/// A spec as written by the LLM, before its dimensions are parsed
+#[derive(Deserialize, Debug)]
+pub struct WrittenSpec {
+    pub object_type: String,
+    pub description: String,
+    #[serde(default)]
+    pub dimensions: Vec<WrittenDimension>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub assumptions: Vec<String>,
+    #[serde(default)]
+    pub open_questions: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WrittenDimension {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpecDimension {
+    pub name: String,
+    pub value: Dimension,
+}
+
+/// A structured description of the model the user asked for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ModelSpec {
+    pub object_type: String,
+    pub description: String,
+    pub dimensions: Vec<SpecDimension>,
+    pub features: Vec<String>,
+    pub assumptions: Vec<String>,
+    pub open_questions: Vec<String>,
+}
+
+fn push_list(text: &mut String, title: &str, items: &[String]) {
+    if items.is_empty() {
+        return;
+    }
+    text.push_str(&format!("\n{title}:\n"));
+    for item in items {
+        text.push_str(&format!("- {item}\n"));
+    }
+}
+
+impl ModelSpec {
+    /// Parses the dimensions of a written spec. Bare numbers are read in
+    /// `display_unit`; dimensions that can't be read become open questions.
+    pub fn from_written(written: WrittenSpec, display_unit: LengthUnit) -> ModelSpec {
+        let mut dimensions = Vec::new();
+        let mut open_questions = written.open_questions;
+
+        for dimension in written.dimensions {
+            match Dimension::parse(&dimension.value, display_unit) {
+                Ok(value) => dimensions.push(SpecDimension {
+                    name: dimension.name,
+                    value,
+                }),
+                Err(err) => {
+                    eprintln!("Dropped spec dimension \"{}\": {}", dimension.name, err);
+                    open_questions.push(format!(
+                        "What should the {} be? (\"{}\" is not a length)",
+                        dimension.name.to_lowercase(),
+                        dimension.value
+                    ));
+                }
+            }
+        }
+
+        ModelSpec {
+            object_type: written.object_type,
+            description: written.description.trim().to_owned(),
+            dimensions,
+            features: written.features,
+            assumptions: written.assumptions,
+            open_questions,
+        }
+    }
+
+    /// A spec holding only a prose description, for when the summary
+    /// couldn't be parsed
+    pub fn from_prose(description: &str) -> ModelSpec {
+        ModelSpec {
+            object_type: "Model".to_owned(),
+            description: description.trim().to_owned(),
+            dimensions: Vec::new(),
+            features: Vec::new(),
+            assumptions: Vec::new(),
+            open_questions: Vec::new(),
+        }
+    }
+
+    /// Renders the spec with every length in `unit`
+    pub fn to_description_in(&self, unit: LengthUnit) -> String {
+        let mut text = format!("{}\n\n{}\n", self.object_type, self.description);
+
+        if !self.dimensions.is_empty() {
+            text.push_str("\nKey dimensions:\n");
+            for dimension in &self.dimensions {
+                text.push_str(&format!(
+                    "- {}: {}\n",
+                    dimension.name,
+                    dimension.value.format(unit)
+                ));
+            }
+        }
+
+        push_list(&mut text, "Features", &self.features);
+        push_list(&mut text, "Assumptions", &self.assumptions);
+        push_list(&mut text, "Open questions", &self.open_questions);
+
+        text
+    }
+
+    /// Renders the spec in inches, for the rest of the chain
+    pub fn to_description(&self) -> String {
+        self.to_description_in(LengthUnit::Inch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRITTEN: &str = r#"
+object_type: Coffee mug
+description: |
+  A plain mug
+dimensions:
+  - name: Outer diameter
+    value: 80 mm
+  - name: Height
+    value: "4"
+  - name: Handle
+    value: comfortable
+features:
+  - Cylindrical body
+"#;
+
+    fn spec(display_unit: LengthUnit) -> ModelSpec {
+        ModelSpec::from_written(serde_yaml::from_str(WRITTEN).unwrap(), display_unit)
+    }
+
+    #[test]
+    fn dimensions_are_parsed_with_their_units() {
+        let spec = spec(LengthUnit::Millimeter);
+        assert_eq!(spec.description, "A plain mug");
+        assert_eq!(spec.dimensions.len(), 2);
+        assert_eq!(spec.dimensions[0].value.unit, LengthUnit::Millimeter);
+        assert!((spec.dimensions[0].value.inches - 80.0 / 25.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bare_numbers_use_the_display_unit() {
+        let inches = spec(LengthUnit::Inch).dimensions[1].value;
+        assert_eq!(inches.unit, LengthUnit::Inch);
+        assert_eq!(inches.inches, 4.0);
+
+        let millimeters = spec(LengthUnit::Millimeter).dimensions[1].value;
+        assert_eq!(millimeters.unit, LengthUnit::Millimeter);
+        assert!((millimeters.inches - 4.0 / 25.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn unreadable_dimensions_become_open_questions() {
+        let spec = spec(LengthUnit::Inch);
+        assert_eq!(
+            spec.open_questions,
+            ["What should the handle be? (\"comfortable\" is not a length)"]
+        );
+    }
+
+    #[test]
+    fn optional_lists_default_to_empty() {
+        let written: WrittenSpec =
+            serde_yaml::from_str("object_type: Cube\ndescription: A cube").unwrap();
+        let spec = ModelSpec::from_written(written, LengthUnit::Inch);
+        assert!(spec.dimensions.is_empty());
+        assert!(spec.features.is_empty());
+        assert!(spec.assumptions.is_empty());
+        assert!(spec.open_questions.is_empty());
+    }
+
+    #[test]
+    fn description_lists_dimensions_in_the_requested_unit() {
+        let description = spec(LengthUnit::Millimeter).to_description();
+        assert!(description.starts_with("Coffee mug\n\nA plain mug\n"));
+        assert!(description.contains("- Outer diameter: 3.1496 in\n"));
+        assert!(description.contains("\nFeatures:\n- Cylindrical body\n"));
+        assert!(!description.contains("Assumptions"));
+    }
+}
@@ -0,0 +1,499 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+
+use crate::chain::agents::executive_planner::ExecutivePlanner;
+use crate::chain::agents::mathematician::MathematicianAgent;
+use crate::chain::agents::onpy_agent::OnPyAgent;
+use crate::chain::agents::pessimist::{PessimistAgent, PessimistOutcome};
+use crate::chain::agents::post_build_reporter::PostBuildReporter;
+use crate::chain::agents::preliminary_reporter::PreliminaryReporter;
+use crate::chain::capabilities;
+use crate::chain::export::ExportedFile;
+use crate::chain::fake_onpy::ExecutionBackend;
+use crate::chain::onshape::{
+    introspect, prepare_build_target, translate_part_studio, BuildMode, BuildTarget,
+};
+use crate::chain::pipeline::{ChainStage, SessionContext};
+use crate::chain::plan_lint::validate_plan;
+use crate::chain::units::{normalize_dimensions, LengthUnit};
+use crate::chain::util::is_affirmative;
+use crate::chain::verification::ExpectedGeometry;
+use crate::server::types::{ReviewAction, ServerResponse, ServerResponseType};
+
+const SPEC_CONFIRMATION: &str =
+    "Does this look right? Reply \"yes\" to start modeling, or tell me what to change.";
+const MAX_SPEC_REVISIONS: usize = 3;
+const MAX_OUTLINE_REVISIONS: usize = 3;
+
+/// Converses with the user until their request is within Polybrain's
+/// capabilities, then has them confirm the resulting spec
+pub struct PessimistStage;
+
+#[async_trait]
+impl ChainStage for PessimistStage {
+    fn name(&self) -> &'static str {
+        "Pessimist"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let mut pessimist = PessimistAgent::new(
+            &ctx.credentials.openai_token,
+            ctx.config.display_unit,
+            ctx.config.pessimist_max_turns,
+        )
+        .with_existing_model(ctx.existing_model_section(ctx.config.display_unit))
+        .with_transcript(ctx.state.pessimist_transcript.clone());
+        let initial_input = ctx.state.initial_input.clone();
+        let outcome = pessimist
+            .run(&initial_input, &ctx.io, &ctx.checkpointer, &mut ctx.state)
+            .await?;
+        let mut spec = match outcome {
+            PessimistOutcome::Accepted(spec) => spec,
+            PessimistOutcome::Rejected { message, reason } => {
+                ctx.io
+                    .send(
+                        ServerResponse::new(ServerResponseType::Rejection, &message)
+                            .with_payload(serde_json::json!({ "reason": reason })),
+                    )
+                    .await?;
+                ctx.state.rejection = Some(reason);
+                return Ok(());
+            }
+        };
+
+        let mut revisions = 0;
+        loop {
+            ctx.io
+                .send(
+                    ServerResponse::new(
+                        ServerResponseType::Spec,
+                        spec.to_description_in(ctx.config.display_unit),
+                    )
+                    .with_payload(serde_json::to_value(&spec)?),
+                )
+                .await?;
+
+            if revisions == MAX_SPEC_REVISIONS {
+                ctx.io.notify("I'll continue with this spec.").await?;
+                break;
+            }
+
+            let reply = ctx.io.ask(SPEC_CONFIRMATION).await?;
+            if is_affirmative(&reply) {
+                break;
+            }
+
+            spec = pessimist.revise(&reply).await?;
+            revisions += 1;
+        }
+
+        ctx.state.parsed_prompt = Some(spec.to_description());
+        ctx.state.model_spec = Some(spec);
+        Ok(())
+    }
+}
+
+/// Derives the numbers the planner will need
+pub struct MathematicianStage;
+
+#[async_trait]
+impl ChainStage for MathematicianStage {
+    fn name(&self) -> &'static str {
+        "Mathematician"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let mathematician =
+            MathematicianAgent::new(&ctx.credentials.openai_token, ctx.model_description());
+        let math_notes = mathematician.run().await?;
+
+        ctx.state.math_notes = Some(math_notes);
+        Ok(())
+    }
+}
+
+/// Writes the outline the OnPy agent will follow
+pub struct ExecutivePlannerStage;
+
+#[async_trait]
+impl ChainStage for ExecutivePlannerStage {
+    fn name(&self) -> &'static str {
+        "ExecutivePlanner"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        plan_model(ctx, None).await
+    }
+}
+
+/// Runs the executive planner, optionally revising the current outline with
+/// the user's feedback, and stores the resulting plan
+async fn plan_model(
+    ctx: &mut SessionContext<'_>,
+    feedback: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let math_notes = ctx.math_notes();
+    let mut executive_planner = ExecutivePlanner::new(
+        &ctx.credentials.openai_token,
+        ctx.model_description(),
+        &math_notes,
+        ctx.config.display_unit,
+    )?
+    .with_existing_model(
+        ctx.existing_model_section(LengthUnit::Inch),
+        ctx.existing_feature_names(),
+    );
+    if let Some(feedback) = feedback {
+        executive_planner = executive_planner.with_revision(ctx.modeler_outline(), feedback);
+    }
+    let plan = executive_planner.run(&ctx.io).await?;
+    let modeler_outline = plan.to_outline();
+    println!("The modeler outline is:\n{}", modeler_outline);
+
+    ctx.io
+        .send(
+            ServerResponse::new(
+                ServerResponseType::Plan,
+                plan.to_outline_in(ctx.config.display_unit),
+            )
+            .with_payload(serde_json::to_value(&plan)?),
+        )
+        .await?;
+
+    ctx.state.modeler_outline = Some(modeler_outline);
+    ctx.state.model_plan = Some(plan);
+    Ok(())
+}
+
+/// Has the user approve, change, or edit the executive outline before
+/// anything is written to Onshape
+pub struct OutlineApprovalStage;
+
+#[async_trait]
+impl ChainStage for OutlineApprovalStage {
+    fn name(&self) -> &'static str {
+        "OutlineApproval"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let mut revisions = 0;
+        loop {
+            let request = ServerResponse::new(ServerResponseType::Approval, ctx.display_outline())
+                .with_payload(serde_json::json!({
+                    "outline": ctx.modeler_outline(),
+                    "plan": ctx.state.model_plan,
+                }));
+            let reply = ctx.io.review(request).await?;
+
+            let action = reply.action.unwrap_or(if is_affirmative(&reply.response) {
+                ReviewAction::Approve
+            } else {
+                ReviewAction::RequestChanges
+            });
+
+            match action {
+                ReviewAction::Approve => return Ok(()),
+                ReviewAction::Edit => {
+                    apply_edited_outline(ctx, &reply.response).await;
+                    return Ok(());
+                }
+                ReviewAction::RequestChanges if revisions == MAX_OUTLINE_REVISIONS => {
+                    ctx.io.notify("I'll continue with this plan.").await?;
+                    return Ok(());
+                }
+                ReviewAction::RequestChanges => {
+                    ctx.io.notify("Revising the plan...").await?;
+                    let feedback = normalize_dimensions(&reply.response, ctx.config.display_unit);
+                    plan_model(ctx, Some(&feedback)).await?;
+                    ctx.checkpointer.save(&ctx.state).await;
+                    revisions += 1;
+                }
+                ReviewAction::Undo | ReviewAction::RevertTo { .. } => {
+                    ctx.io
+                        .notify("There is no earlier plan to go back to.")
+                        .await?;
+                }
+                ReviewAction::Stop => {
+                    ctx.state.rejection = Some("The user stopped at the outline".to_owned());
+                    ctx.io
+                        .notify("Stopping here; your document hasn't been changed.")
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Replaces the outline with one the user edited. An edited plan in the PLAN
+/// FORMAT stays typed; anything else is kept as a free-form outline.
+async fn apply_edited_outline(ctx: &mut SessionContext<'_>, edited: &str) {
+    let capabilities = capabilities::registry().await;
+    let plan = serde_yaml::from_str(edited)
+        .map_err(|err| err.into())
+        .and_then(|plan| validate_plan(plan, capabilities, &ctx.existing_feature_names()));
+
+    match plan {
+        Ok(plan) => {
+            ctx.state.modeler_outline = Some(plan.to_outline());
+            ctx.state.model_plan = Some(plan);
+        }
+        Err(err) => {
+            println!("Edited outline is not a plan, keeping it as text: {}", err);
+            ctx.state.modeler_outline = Some(normalize_dimensions(edited, ctx.config.display_unit));
+            ctx.state.model_plan = None;
+        }
+    }
+}
+
+/// Tells the user, in brief, how the model will be built
+pub struct PreliminaryReporterStage;
+
+#[async_trait]
+impl ChainStage for PreliminaryReporterStage {
+    fn name(&self) -> &'static str {
+        "PreliminaryReporter"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let mut preliminary_reporter =
+            PreliminaryReporter::new(&ctx.credentials.openai_token, ctx.display_outline());
+        preliminary_reporter.run(&ctx.io).await
+    }
+}
+
+/// The part studio the session builds in. It's chosen, and the document
+/// snapshotted, the first time a stage needs it. Fake builds never touch the
+/// document, so they get a target that only names it.
+async fn ensure_build_target(ctx: &mut SessionContext<'_>) -> Result<BuildTarget, Box<dyn Error>> {
+    if let Some(target) = &ctx.state.build_target {
+        return Ok(target.clone());
+    }
+
+    if ctx.config.execution_backend == ExecutionBackend::FakeOnpy {
+        let target = BuildTarget {
+            document_id: ctx.onshape_document_id.clone(),
+            workspace_id: ctx.config.part_studio.workspace_id.clone(),
+            element_id: ctx.config.part_studio.element_id.clone(),
+            part_studio_name: ctx.config.part_studio.part_studio_name.clone(),
+            snapshot_version_id: None,
+            kept_features: (ctx.config.build_mode == BuildMode::EditExisting).then(Vec::new),
+        };
+        ctx.io
+            .notify("This session only simulates builds, so your Onshape document won't change.")
+            .await?;
+        ctx.state.build_target = Some(target.clone());
+        ctx.checkpointer.save(&ctx.state).await;
+        return Ok(target);
+    }
+
+    let target = prepare_build_target(
+        &ctx.credentials,
+        &ctx.onshape_document_id,
+        ctx.checkpointer.session_id(),
+        &ctx.config.part_studio,
+        ctx.config.build_mode,
+    )
+    .await;
+    let target = match target {
+        Ok(target) => target,
+        Err(err) => {
+            ctx.io
+                .notify("I couldn't prepare your Onshape document, so I've left it unchanged.")
+                .await?;
+            return Err(err.into());
+        }
+    };
+
+    let message = match (&target.part_studio_name, ctx.config.build_mode) {
+        (Some(name), BuildMode::EditExisting) => format!(
+            "I saved a version of your document and will edit the part studio \"{}\".",
+            name
+        ),
+        (Some(name), _) => format!(
+            "I saved a version of your document and will build in the part studio \"{}\".",
+            name
+        ),
+        (None, _) => {
+            "I saved a version of your document before replacing its first part studio.".to_owned()
+        }
+    };
+    ctx.io.notify(&message).await?;
+
+    ctx.state.build_target = Some(target.clone());
+    ctx.checkpointer.save(&ctx.state).await;
+    Ok(target)
+}
+
+/// Reads the model already in the part studio, so the request can be treated
+/// as a change to it
+pub struct IntrospectionStage;
+
+#[async_trait]
+impl ChainStage for IntrospectionStage {
+    fn name(&self) -> &'static str {
+        "Introspection"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        if ctx.config.execution_backend == ExecutionBackend::FakeOnpy {
+            println!("fake builds start from an empty part studio; skipping introspection");
+            return Ok(());
+        }
+
+        let target = ensure_build_target(ctx).await?;
+        let existing_model = introspect(&ctx.credentials, &target).await?;
+
+        ctx.io
+            .notify(&format!(
+                "Here's what is in your part studio now:\n{}",
+                existing_model.to_description_in(ctx.config.display_unit)
+            ))
+            .await?;
+        ctx.state.existing_model = Some(existing_model);
+        Ok(())
+    }
+}
+
+/// Generates and executes OnPy code until the user accepts the model
+pub struct OnPyStage;
+
+#[async_trait]
+impl ChainStage for OnPyStage {
+    fn name(&self) -> &'static str {
+        "OnPy"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let target = ensure_build_target(ctx).await?;
+
+        // The plan describes only the change when editing, so there is no
+        // overall size to check against
+        let expected_geometry = match (&ctx.state.model_plan, ctx.config.build_mode) {
+            (_, BuildMode::EditExisting) | (None, _) => None,
+            (Some(plan), _) => ExpectedGeometry::from_plan(plan),
+        };
+
+        let mut onpy_agent = OnPyAgent::new(
+            &ctx.credentials,
+            ctx.modeler_outline().clone(),
+            ctx.model_description().clone(),
+            target,
+        )
+        .with_existing_model(ctx.existing_model_section(LengthUnit::Inch))
+        .with_expected_geometry(expected_geometry)
+        .with_backend(ctx.config.execution_backend)
+        .with_display_unit(ctx.config.display_unit);
+        onpy_agent
+            .run(&ctx.io, &ctx.checkpointer, &mut ctx.state)
+            .await
+    }
+}
+
+/// Exports the built model from Onshape and sends the files to the client
+pub struct ExportStage;
+
+#[async_trait]
+impl ChainStage for ExportStage {
+    fn name(&self) -> &'static str {
+        "Export"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let built = ctx
+            .state
+            .onpy
+            .as_ref()
+            .is_some_and(|progress| !progress.latest_script.is_empty());
+        let Some(target) = ctx.state.build_target.clone().filter(|_| built) else {
+            println!("nothing was built; skipping export");
+            return Ok(());
+        };
+        if ctx.config.execution_backend == ExecutionBackend::FakeOnpy {
+            println!("fake builds have nothing in Onshape to export; skipping export");
+            return Ok(());
+        }
+
+        for format in ctx.config.export_formats.clone() {
+            if ctx.state.exports.iter().any(|file| file.format == format) {
+                continue;
+            }
+
+            ctx.io
+                .notify(&format!(
+                    "Exporting your model as {}...",
+                    format.format_name()
+                ))
+                .await?;
+            let translation = match translate_part_studio(
+                &ctx.credentials,
+                &target,
+                format.format_name(),
+            )
+            .await
+            {
+                Ok(translation) => translation,
+                Err(err) => {
+                    eprintln!("{} export failed: {}", format.format_name(), err);
+                    ctx.io
+                        .notify(&format!(
+                            "I couldn't export your model as {}.",
+                            format.format_name()
+                        ))
+                        .await?;
+                    continue;
+                }
+            };
+
+            let file = ExportedFile {
+                format,
+                name: format!("model.{}", format.extension()),
+                size: translation.contents.len(),
+                external_data_id: translation.external_data_id,
+                url: translation.url,
+            };
+            ctx.io
+                .file_artifact(&file.name, format.media_type(), &translation.contents)
+                .await?;
+            ctx.state.exports.push(file);
+            ctx.checkpointer.save(&ctx.state).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Summarizes what was actually built, for the closing message
+pub struct PostBuildReporterStage;
+
+#[async_trait]
+impl ChainStage for PostBuildReporterStage {
+    fn name(&self) -> &'static str {
+        "PostBuildReporter"
+    }
+
+    async fn run(&self, ctx: &mut SessionContext<'_>) -> Result<(), Box<dyn Error>> {
+        let built = ctx
+            .state
+            .onpy
+            .as_ref()
+            .filter(|progress| !progress.latest_script.is_empty());
+        let Some(progress) = built else {
+            println!("nothing was built; skipping the post-build report");
+            return Ok(());
+        };
+
+        let reporter = PostBuildReporter::new(
+            &ctx.credentials.openai_token,
+            ctx.model_description(),
+            ctx.modeler_outline(),
+            progress,
+            ctx.config.display_unit,
+        );
+        let report = reporter.run().await?;
+
+        ctx.state.build_report = Some(report);
+        Ok(())
+    }
+}
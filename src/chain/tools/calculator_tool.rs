@@ -0,0 +1,100 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use llm_chain::tools::{Describe, Tool, ToolDescription, ToolError};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::chain::calculator::Calculator;
+
+/// Evaluates calculations deterministically. Variables persist between
+/// invocations.
+pub struct CalculatorTool {
+    calculator: Mutex<Calculator>,
+}
+
+impl CalculatorTool {
+    pub fn new() -> Self {
+        CalculatorTool {
+            calculator: Mutex::new(Calculator::new()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CalculatorInput {
+    pub calculation: String,
+}
+
+impl Describe for CalculatorInput {
+    fn describe() -> llm_chain::tools::Format {
+        vec![(
+            "calculation",
+            "One expression or assignment (like `r = 4 / 2`) per line",
+        )
+            .into()]
+        .into()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CalculatorOutput {
+    pub result: String,
+}
+
+impl From<String> for CalculatorOutput {
+    fn from(value: String) -> Self {
+        Self { result: value }
+    }
+}
+
+impl From<CalculatorOutput> for String {
+    fn from(val: CalculatorOutput) -> Self {
+        val.result
+    }
+}
+
+impl Describe for CalculatorOutput {
+    fn describe() -> llm_chain::tools::Format {
+        vec![("result", "The value of each line, or why it failed").into()].into()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CalculatorError {
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+impl ToolError for CalculatorError {}
+
+#[async_trait]
+impl Tool for CalculatorTool {
+    type Input = CalculatorInput;
+
+    type Output = CalculatorOutput;
+
+    type Error = CalculatorError;
+
+    async fn invoke_typed(&self, input: &Self::Input) -> Result<Self::Output, Self::Error> {
+        let result = self.calculator.lock().unwrap().run(&input.calculation);
+        Ok(CalculatorOutput { result })
+    }
+
+    fn description(&self) -> ToolDescription {
+        ToolDescription::new(
+            "Calculator",
+            "Evaluates math exactly. Use this for EVERY number you compute; never do arithmetic in your head.",
+            concat!(
+                "Supports + - * / % ^, parentheses, pi, and the functions sqrt, abs, round, ",
+                "floor, ceil, min, max, hypot(x, y), dist(x1, y1, x2, y2), sin, cos, tan, asin, ",
+                "acos, atan, and atan2(y, x). All angles are in degrees. polygon(sides, radius, rotation) ",
+                "gives the vertices of a regular polygon and bolt_circle(count, radius, start_angle) ",
+                "gives evenly spaced hole centers, both centered on the origin. Assigned variables ",
+                "can be used in later lines and later calculations."
+            ),
+            CalculatorInput::describe(),
+            CalculatorOutput::describe(),
+        )
+    }
+}
@@ -0,0 +1,82 @@
+use async_trait::async_trait;
+use llm_chain::tools::{Describe, Tool, ToolDescription, ToolError};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub struct MathNotes {}
+
+impl MathNotes {
+    pub fn new() -> Self {
+        MathNotes {}
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MathNotesInput {
+    pub notes: String,
+}
+
+impl Describe for MathNotesInput {
+    fn describe() -> llm_chain::tools::Format {
+        vec![(
+            "notes",
+            "Every value the planner needs, with what it is and its units",
+        )
+            .into()]
+        .into()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MathNotesOutput {
+    pub none: (),
+}
+
+impl From<String> for MathNotesOutput {
+    fn from(_: String) -> Self {
+        Self { none: () }
+    }
+}
+
+impl From<MathNotesOutput> for String {
+    fn from(_: MathNotesOutput) -> Self {
+        "None".to_owned()
+    }
+}
+
+impl Describe for MathNotesOutput {
+    fn describe() -> llm_chain::tools::Format {
+        vec![("none", "This tool has no output").into()].into()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MathNotesError {
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+impl ToolError for MathNotesError {}
+
+#[async_trait]
+impl Tool for MathNotes {
+    type Input = MathNotesInput;
+
+    type Output = MathNotesOutput;
+
+    type Error = MathNotesError;
+
+    async fn invoke_typed(&self, _: &Self::Input) -> Result<Self::Output, Self::Error> {
+        Ok(MathNotesOutput { none: () })
+    }
+
+    fn description(&self) -> ToolDescription {
+        ToolDescription::new(
+            "Math Notes",
+            "Submits your finished math notes to the planner",
+            "Only use values that came from the Calculator. Invoke this once, when you are done.",
+            MathNotesInput::describe(),
+            MathNotesOutput::describe(),
+        )
+    }
+}
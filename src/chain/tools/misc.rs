@@ -20,3 +20,16 @@ pub fn deserialize_output(output: &str) -> Result<ToolOutput, Box<dyn Error>> {
     })?;
     Ok(model)
 }
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ToolInvocation {
+    pub command: String,
+    pub input: Value,
+}
+
+/// Parses a tool invocation written by the LLM, before the tool has run
+pub fn deserialize_invocation(invocation: &str) -> Result<ToolInvocation, Box<dyn Error>> {
+    let invocation = invocation.replace("```yaml", "").replace("```", "");
+    let model: ToolInvocation = serde_yaml::from_str(&invocation)?;
+    Ok(model)
+}
@@ -1,3 +1,5 @@
+pub mod calculator_tool;
+pub mod math_notes_tool;
 pub mod misc;
 pub mod report_tool;
 pub mod user_input_tool;
@@ -13,26 +13,12 @@ impl Report {
 
 #[derive(Serialize, Deserialize)]
 pub struct ReportInput {
-    pub content: String,
-}
-
-impl From<&str> for ReportInput {
-    fn from(value: &str) -> Self {
-        Self {
-            content: value.into(),
-        }
-    }
-}
-
-impl From<String> for ReportInput {
-    fn from(value: String) -> Self {
-        Self { content: value }
-    }
+    pub plan: serde_yaml::Value,
 }
 
 impl Describe for ReportInput {
     fn describe() -> llm_chain::tools::Format {
-        vec![("content", "The contents of the report").into()].into()
+        vec![("plan", "The model plan, written in the PLAN FORMAT").into()].into()
     }
 }
 
@@ -89,7 +75,7 @@ impl Tool for Report {
         ToolDescription::new(
             "Report",
             "Submits the final report",
-            "Put the entire contents of the final report into this tool",
+            "Put the entire model plan into this tool. It must follow the PLAN FORMAT exactly",
             ReportInput::describe(),
             ReportOutput::describe(),
         )
@@ -0,0 +1,489 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// A unit of length. OnPy always works in inches; other units are only for
+/// reading user input and displaying results.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LengthUnit {
+    #[serde(rename = "mm")]
+    Millimeter,
+    #[serde(rename = "cm")]
+    Centimeter,
+    #[serde(rename = "m")]
+    Meter,
+    #[default]
+    #[serde(rename = "in")]
+    Inch,
+    #[serde(rename = "ft")]
+    Foot,
+}
+
+/// Spellings of each unit, longest first so that prefixes don't shadow them
+const UNIT_ALIASES: &[(&str, LengthUnit)] = &[
+    ("millimeters", LengthUnit::Millimeter),
+    ("millimetres", LengthUnit::Millimeter),
+    ("millimeter", LengthUnit::Millimeter),
+    ("millimetre", LengthUnit::Millimeter),
+    ("centimeters", LengthUnit::Centimeter),
+    ("centimetres", LengthUnit::Centimeter),
+    ("centimeter", LengthUnit::Centimeter),
+    ("centimetre", LengthUnit::Centimeter),
+    ("meters", LengthUnit::Meter),
+    ("metres", LengthUnit::Meter),
+    ("meter", LengthUnit::Meter),
+    ("metre", LengthUnit::Meter),
+    ("inches", LengthUnit::Inch),
+    ("inch", LengthUnit::Inch),
+    ("feet", LengthUnit::Foot),
+    ("foot", LengthUnit::Foot),
+    ("mm", LengthUnit::Millimeter),
+    ("cm", LengthUnit::Centimeter),
+    ("in", LengthUnit::Inch),
+    ("ft", LengthUnit::Foot),
+    ("m", LengthUnit::Meter),
+    ("\"", LengthUnit::Inch),
+    ("″", LengthUnit::Inch),
+    ("'", LengthUnit::Foot),
+    ("′", LengthUnit::Foot),
+];
+
+impl LengthUnit {
+    pub fn inches_per_unit(self) -> f64 {
+        match self {
+            LengthUnit::Millimeter => 1.0 / 25.4,
+            LengthUnit::Centimeter => 1.0 / 2.54,
+            LengthUnit::Meter => 1.0 / 0.0254,
+            LengthUnit::Inch => 1.0,
+            LengthUnit::Foot => 12.0,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            LengthUnit::Millimeter => "mm",
+            LengthUnit::Centimeter => "cm",
+            LengthUnit::Meter => "m",
+            LengthUnit::Inch => "in",
+            LengthUnit::Foot => "ft",
+        }
+    }
+
+    pub fn to_inches(self, value: f64) -> f64 {
+        value * self.inches_per_unit()
+    }
+
+    pub fn inches_to_unit(self, inches: f64) -> f64 {
+        inches / self.inches_per_unit()
+    }
+
+    /// Formats a length given in inches as a bare number in this unit
+    pub fn format_value(self, inches: f64) -> String {
+        let value = (self.inches_to_unit(inches) * 1e4).round() / 1e4;
+        if value == 0.0 {
+            "0".to_owned()
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Formats a length given in inches in this unit, e.g. `25.4 mm`
+    pub fn format(self, inches: f64) -> String {
+        format!("{} {}", self.format_value(inches), self.symbol())
+    }
+}
+
+impl fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum UnitError {
+    #[error("\"{0}\" does not start with a number")]
+    NoNumber(String),
+
+    #[error("unexpected \"{remainder}\" after the dimension in \"{text}\"")]
+    TrailingText { text: String, remainder: String },
+}
+
+/// Allowed deviation from a nominal dimension, in inches
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    pub plus: f64,
+    pub minus: f64,
+}
+
+/// A length normalized to inches, remembering the unit it was written in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Dimension {
+    pub inches: f64,
+    pub unit: LengthUnit,
+    pub tolerance: Option<Tolerance>,
+}
+
+impl Dimension {
+    /// Parses a whole string as a dimension, such as `25 mm`, `3/4"`,
+    /// `1-1/2 in`, `5' 6"` or `10 ± 0.1 mm`. Bare numbers are read in
+    /// `default_unit`.
+    pub fn parse(text: &str, default_unit: LengthUnit) -> Result<Dimension, UnitError> {
+        let mut cursor = Cursor::new(text.trim());
+        let dimension = cursor
+            .dimension(Some(default_unit))
+            .ok_or_else(|| UnitError::NoNumber(text.to_owned()))?;
+
+        cursor.skip_whitespace();
+        if !cursor.rest().is_empty() {
+            return Err(UnitError::TrailingText {
+                text: text.to_owned(),
+                remainder: cursor.rest().to_owned(),
+            });
+        }
+
+        Ok(dimension)
+    }
+
+    /// Formats the dimension, with its tolerance, in the given unit
+    pub fn format(&self, unit: LengthUnit) -> String {
+        let nominal = unit.format(self.inches);
+        match self.tolerance {
+            None => nominal,
+            Some(Tolerance { plus, minus }) if plus == minus => {
+                format!("{} ± {}", nominal, unit.format(plus))
+            }
+            Some(Tolerance { plus, minus }) => {
+                format!(
+                    "{} +{} / -{}",
+                    nominal,
+                    unit.format(plus),
+                    unit.format(minus)
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format(self.unit))
+    }
+}
+
+/// Whether a unit spelling could also be ordinary text, like "in" or the
+/// apostrophe in "2's", so it's only a unit when written against its number
+fn is_ambiguous_unit(alias: &str) -> bool {
+    alias == "in" || !alias.chars().all(char::is_alphabetic)
+}
+
+/// Reads dimensions from the front of a string
+struct Cursor<'t> {
+    text: &'t str,
+    position: usize,
+    /// Whether the text is prose rather than a lone dimension, so ambiguous
+    /// unit spellings must directly follow their number
+    prose: bool,
+}
+
+impl<'t> Cursor<'t> {
+    fn new(text: &'t str) -> Self {
+        Cursor {
+            text,
+            position: 0,
+            prose: false,
+        }
+    }
+
+    /// A cursor over a message that may mention dimensions among other words
+    fn in_prose(text: &'t str) -> Self {
+        Cursor {
+            prose: true,
+            ..Cursor::new(text)
+        }
+    }
+
+    fn rest(&self) -> &'t str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consumes `token` if the text continues with it
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Runs `parse`, rewinding if it fails
+    fn attempt<T>(&mut self, parse: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        let start = self.position;
+        let result = parse(self);
+        if result.is_none() {
+            self.position = start;
+        }
+        result
+    }
+
+    fn digits(&mut self) -> Option<&'t str> {
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return None;
+        }
+        self.position += length;
+        Some(&rest[..length])
+    }
+
+    /// A fraction such as `3/4`
+    fn fraction(&mut self) -> Option<f64> {
+        self.attempt(|cursor| {
+            let numerator: f64 = cursor.digits()?.parse().ok()?;
+            if !cursor.eat("/") {
+                return None;
+            }
+            let denominator: f64 = cursor.digits()?.parse().ok()?;
+            (denominator != 0.0).then(|| numerator / denominator)
+        })
+    }
+
+    /// A decimal, fraction, or mixed number such as `1 3/4` or `1-3/4`
+    fn number(&mut self) -> Option<f64> {
+        if let Some(fraction) = self.fraction() {
+            return Some(fraction);
+        }
+
+        self.attempt(|cursor| {
+            let start = cursor.position;
+            cursor.digits();
+            if cursor.eat(".") {
+                cursor.digits();
+            }
+            let literal = &cursor.text[start..cursor.position];
+            let whole: f64 = literal.parse().ok()?;
+
+            if !literal.contains('.') {
+                let mixed = cursor.attempt(|cursor| {
+                    if !cursor.eat("-") && !cursor.eat(" ") {
+                        return None;
+                    }
+                    cursor.fraction().filter(|fraction| *fraction < 1.0)
+                });
+                if let Some(fraction) = mixed {
+                    return Some(whole + fraction);
+                }
+            }
+
+            Some(whole)
+        })
+    }
+
+    /// A unit spelling, ending at a word boundary
+    fn unit(&mut self) -> Option<LengthUnit> {
+        self.attempt(|cursor| {
+            let touches_number = cursor.text[..cursor.position]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_ascii_digit())
+                && !cursor.rest().starts_with(char::is_whitespace);
+            cursor.skip_whitespace();
+            let rest = cursor.rest();
+            let (alias, unit) = UNIT_ALIASES.iter().find(|(alias, _)| {
+                let Some(after) = rest.strip_prefix(alias) else {
+                    return false;
+                };
+                let next = after.chars().next();
+                // Symbols may run into a following dimension, as in 3"x4"
+                let at_boundary = if alias.chars().all(char::is_alphabetic) {
+                    !next.is_some_and(char::is_alphanumeric)
+                } else {
+                    !next.is_some_and(|c| c.is_alphabetic() && c != 'x')
+                };
+                at_boundary && (touches_number || !cursor.prose || !is_ambiguous_unit(alias))
+            })?;
+            cursor.position += alias.len();
+            Some(*unit)
+        })
+    }
+
+    /// A number and an optional unit
+    fn length(&mut self) -> Option<(f64, Option<LengthUnit>)> {
+        let value = self.number()?;
+        Some((value, self.unit()))
+    }
+
+    fn tolerance(&mut self) -> Option<(f64, f64, Option<LengthUnit>)> {
+        self.attempt(|cursor| {
+            cursor.skip_whitespace();
+            if cursor.eat("±") || cursor.eat("+/-") || cursor.eat("+-") {
+                cursor.skip_whitespace();
+                let (value, unit) = cursor.length()?;
+                return Some((value, value, unit));
+            }
+
+            if !cursor.eat("+") {
+                return None;
+            }
+            cursor.skip_whitespace();
+            let (plus, plus_unit) = cursor.length()?;
+            cursor.skip_whitespace();
+            if cursor.eat("/") {
+                cursor.skip_whitespace();
+            }
+            if !cursor.eat("-") {
+                return None;
+            }
+            cursor.skip_whitespace();
+            let (minus, minus_unit) = cursor.length()?;
+            Some((plus, minus, minus_unit.or(plus_unit)))
+        })
+    }
+
+    /// A full dimension. Without a `default_unit`, a unit must be written.
+    fn dimension(&mut self, default_unit: Option<LengthUnit>) -> Option<Dimension> {
+        self.attempt(|cursor| {
+            let (value, unit) = cursor.length()?;
+
+            // Feet may be followed by inches, as in 5' 6"
+            let mut extra_inches = 0.0;
+            if unit == Some(LengthUnit::Foot) {
+                let inches = cursor.attempt(|cursor| {
+                    cursor.skip_whitespace();
+                    match cursor.length()? {
+                        (inches, Some(LengthUnit::Inch)) => Some(inches),
+                        _ => None,
+                    }
+                });
+                extra_inches = inches.unwrap_or_default();
+            }
+
+            let tolerance = cursor.tolerance();
+            let unit = unit
+                .or(tolerance.and_then(|(_, _, unit)| unit))
+                .or(default_unit)?;
+            let tolerance_unit = tolerance.and_then(|(_, _, unit)| unit).unwrap_or(unit);
+
+            Some(Dimension {
+                inches: unit.to_inches(value) + extra_inches,
+                unit,
+                tolerance: tolerance.map(|(plus, minus, _)| Tolerance {
+                    plus: tolerance_unit.to_inches(plus),
+                    minus: tolerance_unit.to_inches(minus),
+                }),
+            })
+        })
+    }
+}
+
+/// Annotates every dimension in a user's message with its value in inches,
+/// e.g. `a 25 mm wall` becomes `a 25 mm (0.9843 in) wall`. If the whole
+/// message is a bare number, it is read in `display_unit`.
+pub fn normalize_dimensions(text: &str, display_unit: LengthUnit) -> String {
+    let trimmed = text.trim();
+    if display_unit != LengthUnit::Inch && Cursor::new(trimmed).dimension(None).is_none() {
+        if let Ok(dimension) = Dimension::parse(trimmed, display_unit) {
+            return format!(
+                "{} ({})",
+                dimension.format(display_unit),
+                dimension.format(LengthUnit::Inch)
+            );
+        }
+    }
+
+    let mut normalized = String::with_capacity(text.len());
+    let mut cursor = Cursor::in_prose(text);
+    while !cursor.rest().is_empty() {
+        let previous = cursor.text[..cursor.position].chars().next_back();
+        let starts_number = cursor.rest().starts_with(|c: char| c.is_ascii_digit())
+            && !previous.is_some_and(|c| c.is_alphanumeric() || c == '.');
+
+        if starts_number {
+            let start = cursor.position;
+            if let Some(dimension) = cursor.dimension(None) {
+                let written = &cursor.text[start..cursor.position];
+                normalized.push_str(written);
+                if dimension.unit != LengthUnit::Inch
+                    || dimension.tolerance.is_some()
+                    || written.contains('/')
+                {
+                    normalized.push_str(&format!(" ({})", dimension.format(LengthUnit::Inch)));
+                }
+                continue;
+            }
+            // Skip the whole number so its digits aren't rescanned
+            let literal = cursor.digits().unwrap_or_default();
+            normalized.push_str(literal);
+            continue;
+        }
+
+        let c = cursor.rest().chars().next().unwrap_or_default();
+        normalized.push(c);
+        cursor.position += c.len_utf8();
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions_parse_with_units_and_tolerances() {
+        let parse = |text| Dimension::parse(text, LengthUnit::Millimeter).unwrap();
+        assert_eq!(parse("2 in").inches, 2.0);
+        assert_eq!(parse("1-1/2\"").inches, 1.5);
+        assert_eq!(parse("5' 6\"").inches, 66.0);
+        assert!((parse("25.4").inches - 1.0).abs() < 1e-9);
+        assert_eq!(
+            parse("10 ± 0.1 in").tolerance,
+            Some(Tolerance {
+                plus: 0.1,
+                minus: 0.1
+            })
+        );
+        assert!(Dimension::parse("wide", LengthUnit::Inch).is_err());
+        assert!(Dimension::parse("3 in wide", LengthUnit::Inch).is_err());
+    }
+
+    #[test]
+    fn dimensions_in_messages_are_annotated() {
+        let normalize = |text| normalize_dimensions(text, LengthUnit::Inch);
+        assert_eq!(normalize("a 25 mm wall"), "a 25 mm (0.9843 in) wall");
+        assert_eq!(normalize("a 3in hole"), "a 3in hole");
+        assert_eq!(normalize("a 1/2\" rod"), "a 1/2\" (0.5 in) rod");
+        assert_eq!(normalize("5'6\" tall"), "5'6\" (66 in) tall");
+        assert_eq!(normalize("a 3\"x4\" plate"), "a 3\"x4\" plate");
+    }
+
+    #[test]
+    fn ordinary_words_are_not_units() {
+        let normalize = |text| normalize_dimensions(text, LengthUnit::Inch);
+        for text in [
+            "add 2's and 3's to the face",
+            "put 3 in a row",
+            "it has 4 mmm flavors",
+            "version 2 'final'",
+        ] {
+            assert_eq!(normalize(text), text);
+        }
+    }
+
+    #[test]
+    fn a_bare_number_reply_uses_the_display_unit() {
+        assert_eq!(
+            normalize_dimensions("25", LengthUnit::Millimeter),
+            "25 mm (0.9843 in)"
+        );
+        assert_eq!(normalize_dimensions("25", LengthUnit::Inch), "25");
+    }
+}
@@ -7,3 +7,64 @@ pub fn trim_assistant_prefix(s: &str) -> &str {
         s
     }
 }
+
+/// Whether a user's reply accepts what they were shown, rather than asking
+/// for changes. Only bare confirmations count, alone or joined by commas;
+/// anything more, like "yes, but make it taller", may be asking for a change.
+pub fn is_affirmative(reply: &str) -> bool {
+    let reply = reply.trim().trim_end_matches(['.', '!']).to_lowercase();
+    reply.split(',').all(|part| {
+        matches!(
+            part.trim(),
+            "y" | "yes"
+                | "yep"
+                | "yeah"
+                | "ok"
+                | "okay"
+                | "sure"
+                | "correct"
+                | "confirm"
+                | "looks good"
+                | "lgtm"
+                | "go ahead"
+                | "build it"
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_confirmations_are_affirmative() {
+        for reply in [
+            "yes",
+            "Y",
+            "  Okay! ",
+            "looks good.",
+            "LGTM",
+            "yes, build it",
+        ] {
+            assert!(is_affirmative(reply), "{reply:?} should be affirmative");
+        }
+    }
+
+    #[test]
+    fn changes_and_hedges_are_not_affirmative() {
+        for reply in [
+            "no",
+            "",
+            "yesterday's size",
+            "ok but make it taller",
+            "yes, but make it 2 inches taller",
+            "yes make it blue",
+            "make it 2 inches wider",
+        ] {
+            assert!(
+                !is_affirmative(reply),
+                "{reply:?} should not be affirmative"
+            );
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chain::existing_model::BoundingBox;
+use crate::chain::plan::{BasePlane, ExtrudeOperation, ModelPlan, PlannedFeature, SketchEntity};
+use crate::chain::units::LengthUnit;
+
+/// Relative difference allowed between measured and planned sizes
+const SIZE_TOLERANCE: f64 = 0.02;
+/// Absolute difference always allowed, in inches, for very small features
+const MIN_SIZE_TOLERANCE: f64 = 0.01;
+
+/// What one built part measured, in inches
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PartGeometry {
+    pub name: String,
+    pub bounding_box: BoundingBox,
+    /// In cubic inches
+    pub volume: f64,
+    pub face_count: usize,
+}
+
+/// What a build produced, as measured in Onshape
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BuildGeometry {
+    pub parts: Vec<PartGeometry>,
+}
+
+impl BuildGeometry {
+    /// The size of the box around every part, along x, y, and z
+    pub fn overall_size(&self) -> Option<[f64; 3]> {
+        let mut parts = self.parts.iter();
+        let first = parts.next()?;
+        let (mut low, mut high) = (first.bounding_box.low, first.bounding_box.high);
+        for part in parts {
+            for axis in 0..3 {
+                low[axis] = low[axis].min(part.bounding_box.low[axis]);
+                high[axis] = high[axis].max(part.bounding_box.high[axis]);
+            }
+        }
+        Some([0, 1, 2].map(|axis| high[axis] - low[axis]))
+    }
+
+    pub fn to_description_in(&self, unit: LengthUnit) -> String {
+        if self.parts.is_empty() {
+            return "No parts were built.".to_owned();
+        }
+
+        let mut text = String::new();
+        for part in &self.parts {
+            let [x, y, z] = part.bounding_box.size();
+            text.push_str(&format!(
+                "- {}: {} x {} x {}, {} faces, volume {:.3} {}³\n",
+                part.name,
+                unit.format(x),
+                unit.format(y),
+                unit.format(z),
+                part.face_count,
+                part.volume / unit.inches_per_unit().powi(3),
+                unit
+            ));
+        }
+        text
+    }
+}
+
+/// The part count and overall size a plan should produce
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectedGeometry {
+    /// Only known when the plan states it, since new features can merge
+    pub part_count: Option<usize>,
+    /// Along x, y, and z
+    pub size: [f64; 3],
+}
+
+/// Accumulates the box around the material a plan adds
+struct Extents {
+    low: [f64; 3],
+    high: [f64; 3],
+}
+
+impl Extents {
+    fn new() -> Extents {
+        Extents {
+            low: [f64::INFINITY; 3],
+            high: [f64::NEG_INFINITY; 3],
+        }
+    }
+
+    fn include(&mut self, point: [f64; 3]) {
+        for (axis, value) in point.into_iter().enumerate() {
+            self.low[axis] = self.low[axis].min(value);
+            self.high[axis] = self.high[axis].max(value);
+        }
+    }
+
+    fn size(&self) -> Option<[f64; 3]> {
+        if self.low.iter().any(|value| !value.is_finite()) {
+            return None;
+        }
+        Some([0, 1, 2].map(|axis| self.high[axis] - self.low[axis]))
+    }
+}
+
+/// Maps a sketch point and a distance along the plane's normal to world
+/// coordinates, following Onshape's default planes
+fn to_world(plane: BasePlane, point: [f64; 2], normal: f64) -> [f64; 3] {
+    let [u, v] = point;
+    match plane {
+        BasePlane::Top => [u, v, normal],
+        BasePlane::Front => [u, -normal, v],
+        BasePlane::Right => [normal, u, v],
+    }
+}
+
+/// The corners of the 2D box around a sketch's entities
+fn sketch_extents(entities: &[SketchEntity]) -> Vec<[f64; 2]> {
+    let mut points = Vec::new();
+    for entity in entities {
+        match entity {
+            SketchEntity::Line { start, end } => points.extend([*start, *end]),
+            SketchEntity::Circle { center, radius } => points.extend([
+                [center[0] - radius, center[1] - radius],
+                [center[0] + radius, center[1] + radius],
+            ]),
+            SketchEntity::CenterpointArc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+            } => {
+                let end_angle = if end_angle < start_angle {
+                    end_angle + 360.0
+                } else {
+                    *end_angle
+                };
+                let at = |angle: f64| {
+                    let radians = angle.to_radians();
+                    [
+                        center[0] + radius * radians.cos(),
+                        center[1] + radius * radians.sin(),
+                    ]
+                };
+                points.extend([at(*start_angle), at(end_angle)]);
+                // The arc also reaches every axis it sweeps past
+                let mut quadrant = (start_angle / 90.0).ceil() * 90.0;
+                while quadrant < end_angle {
+                    points.push(at(quadrant));
+                    quadrant += 90.0;
+                }
+            }
+            SketchEntity::Fillet { .. } => {}
+        }
+    }
+    points
+}
+
+impl ExpectedGeometry {
+    /// Works out what a plan should build. Returns `None` if the plan adds no
+    /// material, or if a feature's sketch shares its name with another, since
+    /// then there's no telling which one it uses.
+    pub fn from_plan(plan: &ModelPlan) -> Option<ExpectedGeometry> {
+        let mut extents = Extents::new();
+
+        for feature in &plan.features {
+            let sketches = match feature {
+                PlannedFeature::Extrude {
+                    sketch,
+                    distance,
+                    operation,
+                    ..
+                } => match operation {
+                    ExtrudeOperation::New | ExtrudeOperation::Add => vec![(sketch, *distance)],
+                    ExtrudeOperation::Subtract => Vec::new(),
+                },
+                PlannedFeature::Loft {
+                    start_sketch,
+                    end_sketch,
+                    ..
+                } => vec![(start_sketch, 0.0), (end_sketch, 0.0)],
+            };
+
+            for (name, distance) in sketches {
+                let mut named = plan.sketches.iter().filter(|sketch| &sketch.name == name);
+                let sketch = match (named.next(), named.next()) {
+                    (Some(sketch), None) => sketch,
+                    (Some(_), Some(_)) => return None,
+                    (None, _) => continue,
+                };
+                let offset = sketch.plane.offset;
+                for point in sketch_extents(&sketch.entities) {
+                    extents.include(to_world(sketch.plane.base, point, offset));
+                    extents.include(to_world(sketch.plane.base, point, offset + distance));
+                }
+            }
+        }
+
+        Some(ExpectedGeometry {
+            part_count: plan.part_count,
+            size: extents.size()?,
+        })
+    }
+}
+
+fn within_tolerance(measured: f64, expected: f64) -> bool {
+    (measured - expected).abs() <= (expected.abs() * SIZE_TOLERANCE).max(MIN_SIZE_TOLERANCE)
+}
+
+/// Compares a build against what was expected, describing every mismatch.
+/// Without an expectation, only checks that solid parts were built.
+pub fn verify(geometry: &BuildGeometry, expected: Option<&ExpectedGeometry>) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    if geometry.parts.is_empty() {
+        mismatches.push("The script ran, but no parts were built.".to_owned());
+        return mismatches;
+    }
+
+    for part in &geometry.parts {
+        if part.volume <= 0.0 || part.face_count == 0 {
+            mismatches.push(format!("Part \"{}\" has no volume.", part.name));
+        }
+    }
+
+    if let Some(expected) = expected {
+        mismatches.extend(compare(
+            Some(geometry.parts.len()),
+            geometry.overall_size(),
+            expected,
+        ));
+    }
+
+    mismatches
+}
+
+/// Compares what a script tried to build, worked out from its trace rather
+/// than measured, against what was expected
+pub fn verify_attempt(
+    attempted: Option<&ExpectedGeometry>,
+    expected: Option<&ExpectedGeometry>,
+) -> Vec<String> {
+    let Some(attempted) = attempted else {
+        return vec!["The script ran, but built no solid features.".to_owned()];
+    };
+    match expected {
+        Some(expected) => compare(attempted.part_count, Some(attempted.size), expected),
+        None => Vec::new(),
+    }
+}
+
+/// Compares whatever is known of a build against what was expected
+fn compare(
+    part_count: Option<usize>,
+    size: Option<[f64; 3]>,
+    expected: &ExpectedGeometry,
+) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    if let (Some(part_count), Some(expected_count)) = (part_count, expected.part_count) {
+        if part_count != expected_count {
+            mismatches.push(format!(
+                "The plan makes {} part(s), but {} were built.",
+                expected_count, part_count
+            ));
+        }
+    }
+
+    if let Some(measured) = size {
+        for (axis, name) in ["X", "Y", "Z"].into_iter().enumerate() {
+            if !within_tolerance(measured[axis], expected.size[axis]) {
+                mismatches.push(format!(
+                    "The model is {} along {}, but the plan makes it {}.",
+                    LengthUnit::Inch.format(measured[axis]),
+                    name,
+                    LengthUnit::Inch.format(expected.size[axis])
+                ));
+            }
+        }
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = r#"
+summary: A plate with a boss
+sketches:
+  - name: Plate
+    plane:
+      base: Top
+    entities:
+      - type: line
+        start: [0.0, 0.0]
+        end: [4.0, 2.0]
+  - name: Boss
+    plane:
+      base: Top
+      offset: 0.5
+    entities:
+      - type: circle
+        center: [2.0, 1.0]
+        radius: 0.5
+  - name: Hole
+    plane:
+      base: Front
+    entities:
+      - type: circle
+        center: [0.0, 0.0]
+        radius: 10.0
+features:
+  - type: extrude
+    name: Plate
+    sketch: Plate
+    distance: 0.5
+  - type: extrude
+    name: Boss
+    sketch: Boss
+    distance: 1.0
+    operation: add
+    target: Plate
+  - type: extrude
+    name: Hole
+    sketch: Hole
+    distance: 1.0
+    operation: subtract
+    target: Plate
+"#;
+
+    fn plan() -> ModelPlan {
+        serde_yaml::from_str(PLAN).unwrap()
+    }
+
+    fn geometry(sizes: &[[f64; 3]]) -> BuildGeometry {
+        BuildGeometry {
+            parts: sizes
+                .iter()
+                .enumerate()
+                .map(|(index, size)| PartGeometry {
+                    name: format!("Part {}", index + 1),
+                    bounding_box: BoundingBox {
+                        low: [0.0; 3],
+                        high: *size,
+                    },
+                    volume: size.iter().product(),
+                    face_count: 6,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn expected_size_covers_added_material_only() {
+        let expected = ExpectedGeometry::from_plan(&plan()).unwrap();
+        assert_eq!(expected.size, [4.0, 2.0, 1.5]);
+        assert_eq!(expected.part_count, None);
+    }
+
+    #[test]
+    fn part_count_comes_from_the_plan() {
+        let mut plan = plan();
+        plan.part_count = Some(1);
+        assert_eq!(
+            ExpectedGeometry::from_plan(&plan).unwrap().part_count,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn plans_without_material_have_no_expectation() {
+        let mut plan = plan();
+        plan.features.retain(|feature| {
+            matches!(
+                feature,
+                PlannedFeature::Extrude {
+                    operation: ExtrudeOperation::Subtract,
+                    ..
+                }
+            )
+        });
+        assert_eq!(ExpectedGeometry::from_plan(&plan), None);
+    }
+
+    #[test]
+    fn ambiguous_sketch_names_have_no_expectation() {
+        let mut plan = plan();
+        plan.sketches[1].name = "Plate".to_owned();
+        assert_eq!(ExpectedGeometry::from_plan(&plan), None);
+    }
+
+    #[test]
+    fn matching_builds_pass() {
+        let expected = ExpectedGeometry {
+            part_count: Some(1),
+            size: [4.0, 2.0, 1.5],
+        };
+        assert!(verify(&geometry(&[[4.05, 1.99, 1.5]]), Some(&expected)).is_empty());
+    }
+
+    #[test]
+    fn part_counts_are_only_checked_when_planned() {
+        let build = geometry(&[[4.0, 2.0, 0.5], [1.0, 1.0, 1.5]]);
+        let mut expected = ExpectedGeometry {
+            part_count: None,
+            size: [4.0, 2.0, 1.5],
+        };
+        assert!(verify(&build, Some(&expected)).is_empty());
+
+        expected.part_count = Some(1);
+        assert_eq!(
+            verify(&build, Some(&expected)),
+            ["The plan makes 1 part(s), but 2 were built."]
+        );
+    }
+
+    #[test]
+    fn size_and_volume_mismatches_are_reported() {
+        let expected = ExpectedGeometry {
+            part_count: None,
+            size: [4.0, 2.0, 1.5],
+        };
+        let mut build = geometry(&[[4.0, 2.0, 3.0]]);
+        build.parts[0].face_count = 0;
+        assert_eq!(
+            verify(&build, Some(&expected)),
+            [
+                "Part \"Part 1\" has no volume.".to_owned(),
+                format!(
+                    "The model is {} along Z, but the plan makes it {}.",
+                    LengthUnit::Inch.format(3.0),
+                    LengthUnit::Inch.format(1.5)
+                ),
+            ]
+        );
+        assert_eq!(
+            verify(&geometry(&[]), None),
+            ["The script ran, but no parts were built."]
+        );
+    }
+}
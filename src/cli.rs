@@ -0,0 +1,117 @@
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::chain::{
+    chain_entry::enter_chain,
+    checkpoint::{Checkpointer, SessionState},
+    pipeline::ChainConfig,
+    session_io::{ScriptedTransport, SessionIo, SessionTransport},
+};
+use crate::server::types::{ApiCredentials, ServerResponse, UserInputResponse};
+use crate::util::get_dotenv;
+
+/// Carries session IO over stdin and stdout
+struct TerminalTransport;
+
+impl TerminalTransport {
+    fn print(message: &ServerResponse) {
+        // Binary files are only named, not dumped to the terminal
+        match &message.payload {
+            Some(payload) if payload["encoding"] == "base64" => println!(
+                "\n[{:?}] {} ({} base64 characters)",
+                message.response_type,
+                payload["name"].as_str().unwrap_or_default(),
+                message.content.len()
+            ),
+            _ => println!("\n[{:?}] {}", message.response_type, message.content),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionTransport for TerminalTransport {
+    async fn ask(&self, query: ServerResponse) -> Result<UserInputResponse, Box<dyn Error>> {
+        Self::print(&query);
+        print!("> ");
+        io::stdout().flush()?;
+
+        let response = tokio::task::spawn_blocking(|| {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).map(|_| line)
+        })
+        .await??;
+
+        Ok(UserInputResponse {
+            response: response.trim().to_owned(),
+            action: None,
+        })
+    }
+
+    async fn send(&self, message: ServerResponse) -> Result<(), Box<dyn Error>> {
+        Self::print(&message);
+        Ok(())
+    }
+}
+
+/// Runs a single session from the terminal, using credentials from .env
+///
+/// Usage: `polybrain-core --cli <onshape document id> <prompt> [answers file]`
+///
+/// When an answers file is given, each line answers one query and the full
+/// transcript is printed at the end instead of prompting on stdin.
+pub async fn run_cli(args: &[String]) -> io::Result<()> {
+    let (document_id, prompt) = match args {
+        [document_id, prompt, ..] => (document_id.clone(), prompt.clone()),
+        _ => {
+            eprintln!("usage: polybrain-core --cli <onshape document id> <prompt> [answers file]");
+            return Ok(());
+        }
+    };
+
+    let credentials = ApiCredentials {
+        openai_token: get_dotenv("OPENAI_API_KEY"),
+        onshape_access_key: get_dotenv("ONSHAPE_DEV_ACCESS"),
+        onshape_secret_key: get_dotenv("ONSHAPE_DEV_SECRET"),
+    };
+
+    let scripted = match args.get(2) {
+        Some(path) => {
+            let answers = std::fs::read_to_string(path)?
+                .lines()
+                .map(str::to_owned)
+                .collect();
+            Some(Arc::new(ScriptedTransport::new(answers)))
+        }
+        None => None,
+    };
+
+    let io = match &scripted {
+        Some(transport) => SessionIo::new(transport.clone()),
+        None => SessionIo::new(Arc::new(TerminalTransport)),
+    };
+
+    let result = enter_chain(
+        SessionState::new(&prompt),
+        credentials,
+        document_id,
+        ChainConfig::default(),
+        io,
+        Checkpointer::disabled(),
+    )
+    .await;
+
+    if let Some(transport) = scripted {
+        for message in transport.transcript() {
+            TerminalTransport::print(&message);
+        }
+    }
+
+    if let Err(err) = result {
+        eprintln!("LLM Chain Crashed with error: {}", err);
+    }
+
+    Ok(())
+}
@@ -6,6 +6,8 @@ use tokio::net::TcpListener;
 use util::get_dotenv;
 
 mod chain;
+mod cli;
+mod onshape;
 mod server;
 mod util;
 
@@ -13,6 +15,15 @@ mod util;
 
 async fn main() -> Result<()> {
     dotenv().ok();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--cli") {
+        return cli::run_cli(&args[2..]).await;
+    }
+    if args.get(1).map(String::as_str) == Some("--mock-onshape") {
+        return onshape::mock::run_mock_server(&args[2..]).await;
+    }
+
     let address = format!("{}:{}", get_dotenv("HOST_NAME"), get_dotenv("HOST_PORT"));
 
     println!("connecting to address '{}'...", address);
@@ -0,0 +1,655 @@
+use std::time::Duration;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use hmac::{Hmac, Mac};
+use reqwest::{Method, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::onshape::error::OnshapeError;
+use crate::onshape::types::{
+    BodyDetails, BoundingBoxInfo, Document, ElementInfo, FeatureInfo, FeatureList, MassProperties,
+    Page, PartInfo, TranslationStatus, Version, Workspace,
+};
+use crate::server::types::{onshape_api, ApiCredentials};
+
+/// How many times a rate-limited request is retried before giving up
+const MAX_RATE_LIMIT_RETRIES: u32 = 4;
+/// How long to wait after being rate limited when Onshape doesn't say
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// The longest a Retry-After header can make a request wait
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+const CONTENT_TYPE: &str = "application/json";
+
+/// Onshape's HMAC-SHA256 request signature, over the parts of the request
+/// Onshape checks, encoded as base64
+pub fn sign(
+    secret_key: &str,
+    method: &str,
+    nonce: &str,
+    date: &str,
+    content_type: &str,
+    path: &str,
+    query: &str,
+) -> String {
+    let message = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n",
+        method, nonce, date, content_type, path, query
+    )
+    .to_lowercase();
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret_key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    BASE64_STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// Signs and sends requests to Onshape's REST API with a user's API keys
+pub struct OnshapeClient {
+    http: reqwest::Client,
+    base_url: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl OnshapeClient {
+    pub fn new(credentials: &ApiCredentials) -> OnshapeClient {
+        OnshapeClient {
+            http: reqwest::Client::new(),
+            base_url: onshape_api(),
+            access_key: credentials.onshape_access_key.clone(),
+            secret_key: credentials.onshape_secret_key.clone(),
+        }
+    }
+
+    fn url(&self, path: &str, query: &[(&str, &str)]) -> Result<Url, OnshapeError> {
+        let url = format!("{}/{}", self.base_url, path);
+        Url::parse_with_params(&url, query).map_err(|_| OnshapeError::Malformed("request URL"))
+    }
+
+    /// The Authorization header for a request
+    fn authorization(&self, method: &Method, url: &Url, date: &str, nonce: &str) -> String {
+        let signature = sign(
+            &self.secret_key,
+            method.as_str(),
+            nonce,
+            date,
+            CONTENT_TYPE,
+            url.path(),
+            url.query().unwrap_or(""),
+        );
+        format!("On {}:HmacSHA256:{}", self.access_key, signature)
+    }
+
+    /// Sends a signed request, waiting and retrying while Onshape is rate
+    /// limiting. Fails on any unsuccessful status.
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<&Value>,
+    ) -> Result<Response, OnshapeError> {
+        let mut retry_delay = DEFAULT_RETRY_DELAY;
+
+        for attempt in 0..=MAX_RATE_LIMIT_RETRIES {
+            let date = chrono::Utc::now()
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string();
+            let nonce = uuid::Uuid::new_v4().simple().to_string();
+
+            let mut request = self
+                .http
+                .request(method.clone(), url.clone())
+                .header("Date", &date)
+                .header("On-Nonce", &nonce)
+                .header("Content-Type", CONTENT_TYPE)
+                .header(
+                    "Authorization",
+                    self.authorization(&method, &url, &date, &nonce),
+                );
+            if let Some(body) = body {
+                request = request.body(body.to_string());
+            }
+
+            let response = request.send().await?;
+            let status = response.status();
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                if attempt == MAX_RATE_LIMIT_RETRIES {
+                    break;
+                }
+                let wait = response
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok())
+                    .map(Duration::from_secs)
+                    .unwrap_or(retry_delay)
+                    .min(MAX_RETRY_DELAY);
+                eprintln!(
+                    "Onshape rate limited {} {}; retrying in {}s",
+                    method,
+                    url.path(),
+                    wait.as_secs()
+                );
+                tokio::time::sleep(wait).await;
+                retry_delay *= 2;
+                continue;
+            }
+
+            if !status.is_success() {
+                return Err(OnshapeError::Status {
+                    status: status.as_u16(),
+                    message: response.text().await.unwrap_or_default(),
+                });
+            }
+            return Ok(response);
+        }
+
+        Err(OnshapeError::RateLimited)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        what: &'static str,
+    ) -> Result<T, OnshapeError> {
+        let url = self.url(path, query)?;
+        let value: Value = self.send(Method::GET, url, None).await?.json().await?;
+        serde_json::from_value(value).map_err(|_| OnshapeError::Malformed(what))
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &Value,
+        what: &'static str,
+    ) -> Result<T, OnshapeError> {
+        let url = self.url(path, &[])?;
+        let value: Value = self
+            .send(Method::POST, url, Some(body))
+            .await?
+            .json()
+            .await?;
+        serde_json::from_value(value).map_err(|_| OnshapeError::Malformed(what))
+    }
+
+    /// Gets every item of a paginated list, following `next` links until
+    /// `limit` items are collected or the list ends
+    async fn get_pages<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        limit: Option<usize>,
+        what: &'static str,
+    ) -> Result<Vec<T>, OnshapeError> {
+        let mut items = Vec::new();
+        let mut url = Some(self.url(path, query)?);
+
+        while let Some(page_url) = url {
+            let value: Value = self.send(Method::GET, page_url, None).await?.json().await?;
+            let page: Page<T> =
+                serde_json::from_value(value).map_err(|_| OnshapeError::Malformed(what))?;
+            items.extend(page.items);
+
+            if let Some(limit) = limit.filter(|limit| items.len() >= *limit) {
+                items.truncate(limit);
+                break;
+            }
+            url = match page.next {
+                Some(next) => {
+                    Some(Url::parse(&next).map_err(|_| OnshapeError::Malformed("next page URL"))?)
+                }
+                None => None,
+            };
+        }
+
+        Ok(items)
+    }
+
+    /// The documents the keys can see, up to `limit` of them
+    pub async fn documents(&self, limit: Option<usize>) -> Result<Vec<Document>, OnshapeError> {
+        let page_size = limit.unwrap_or(20).clamp(1, 20).to_string();
+        self.get_pages(
+            "documents",
+            &[("limit", page_size.as_str())],
+            limit,
+            "document list",
+        )
+        .await
+    }
+
+    pub async fn document(&self, document_id: &str) -> Result<Document, OnshapeError> {
+        self.get(&format!("documents/{}", document_id), &[], "document")
+            .await
+    }
+
+    pub async fn workspaces(&self, document_id: &str) -> Result<Vec<Workspace>, OnshapeError> {
+        self.get(
+            &format!("documents/d/{}/workspaces", document_id),
+            &[],
+            "workspace list",
+        )
+        .await
+    }
+
+    /// The part studios in a workspace
+    pub async fn part_studios(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+    ) -> Result<Vec<ElementInfo>, OnshapeError> {
+        self.get(
+            &format!("documents/d/{}/w/{}/elements", document_id, workspace_id),
+            &[("elementType", "PARTSTUDIO")],
+            "element list",
+        )
+        .await
+    }
+
+    /// Adds an empty part studio to a workspace
+    pub async fn create_part_studio(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        name: &str,
+    ) -> Result<ElementInfo, OnshapeError> {
+        self.post(
+            &format!("partstudios/d/{}/w/{}", document_id, workspace_id),
+            &json!({ "name": name }),
+            "element",
+        )
+        .await
+    }
+
+    /// The features of a part studio, in order
+    pub async fn features(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+    ) -> Result<Vec<FeatureInfo>, OnshapeError> {
+        let list: FeatureList = self
+            .get(
+                &format!(
+                    "partstudios/d/{}/w/{}/e/{}/features",
+                    document_id, workspace_id, element_id
+                ),
+                &[],
+                "features",
+            )
+            .await?;
+        Ok(list.features)
+    }
+
+    pub async fn delete_feature(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+        feature_id: &str,
+    ) -> Result<(), OnshapeError> {
+        let url = self.url(
+            &format!(
+                "partstudios/d/{}/w/{}/e/{}/features/featureid/{}",
+                document_id, workspace_id, element_id, feature_id
+            ),
+            &[],
+        )?;
+        self.send(Method::DELETE, url, None).await?;
+        Ok(())
+    }
+
+    /// The parts a part studio produces
+    pub async fn parts(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+    ) -> Result<Vec<PartInfo>, OnshapeError> {
+        self.get(
+            &format!(
+                "parts/d/{}/w/{}/e/{}",
+                document_id, workspace_id, element_id
+            ),
+            &[],
+            "part list",
+        )
+        .await
+    }
+
+    /// A part's volume, in cubic meters
+    pub async fn part_volume(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+        part_id: &str,
+    ) -> Result<f64, OnshapeError> {
+        let properties: MassProperties = self
+            .get(
+                &format!(
+                    "parts/d/{}/w/{}/e/{}/partid/{}/massproperties",
+                    document_id, workspace_id, element_id, part_id
+                ),
+                &[],
+                "mass properties",
+            )
+            .await?;
+
+        properties
+            .bodies
+            .get(part_id)
+            .and_then(|body| body.volume.first().copied())
+            .ok_or(OnshapeError::Malformed("volume"))
+    }
+
+    pub async fn part_face_count(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+        part_id: &str,
+    ) -> Result<usize, OnshapeError> {
+        let details: BodyDetails = self
+            .get(
+                &format!(
+                    "parts/d/{}/w/{}/e/{}/partid/{}/bodydetails",
+                    document_id, workspace_id, element_id, part_id
+                ),
+                &[],
+                "bodies",
+            )
+            .await?;
+        Ok(details.bodies.iter().map(|body| body.faces.len()).sum())
+    }
+
+    pub async fn part_bounding_box(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+        part_id: &str,
+    ) -> Result<BoundingBoxInfo, OnshapeError> {
+        self.get(
+            &format!(
+                "parts/d/{}/w/{}/e/{}/partid/{}/boundingboxes",
+                document_id, workspace_id, element_id, part_id
+            ),
+            // The default box is only approximate and can be noticeably
+            // larger than the part
+            &[("precise", "true")],
+            "bounding box",
+        )
+        .await
+    }
+
+    /// Saves the current state of a workspace as a named version
+    pub async fn create_version(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        name: &str,
+        description: &str,
+    ) -> Result<Version, OnshapeError> {
+        self.post(
+            &format!("documents/d/{}/versions", document_id),
+            &json!({
+                "documentId": document_id,
+                "workspaceId": workspace_id,
+                "name": name,
+                "description": description,
+            }),
+            "version",
+        )
+        .await
+    }
+
+    /// Starts translating a part studio into `format_name`
+    pub async fn start_translation(
+        &self,
+        document_id: &str,
+        workspace_id: &str,
+        element_id: &str,
+        format_name: &str,
+    ) -> Result<TranslationStatus, OnshapeError> {
+        self.post(
+            &format!(
+                "partstudios/d/{}/w/{}/e/{}/translations",
+                document_id, workspace_id, element_id
+            ),
+            &json!({
+                "formatName": format_name,
+                "storeInDocument": false,
+            }),
+            "translation",
+        )
+        .await
+    }
+
+    pub async fn translation_status(
+        &self,
+        translation_id: &str,
+    ) -> Result<TranslationStatus, OnshapeError> {
+        self.get(
+            &format!("translations/{}", translation_id),
+            &[],
+            "translation status",
+        )
+        .await
+    }
+
+    /// Where a file Onshape produced can be downloaded from, with the keys
+    /// that produced it
+    pub fn external_data_url(&self, document_id: &str, file_id: &str) -> Result<Url, OnshapeError> {
+        self.url(
+            &format!("documents/d/{}/externaldata/{}", document_id, file_id),
+            &[],
+        )
+    }
+
+    /// Downloads a file Onshape produced, such as a finished translation
+    pub async fn download_external_data(
+        &self,
+        document_id: &str,
+        file_id: &str,
+    ) -> Result<Vec<u8>, OnshapeError> {
+        let url = self.external_data_url(document_id, file_id)?;
+        let response = self.send(Method::GET, url, None).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::onshape::mock::MockServer;
+
+    const ACCESS_KEY: &str = "test-access";
+    const SECRET_KEY: &str = "test-secret";
+
+    async fn client_and_mock() -> (OnshapeClient, MockServer) {
+        let mock = MockServer::start("127.0.0.1:0", ACCESS_KEY, SECRET_KEY)
+            .await
+            .unwrap();
+        let client = OnshapeClient {
+            http: reqwest::Client::new(),
+            base_url: mock.api_url(),
+            access_key: ACCESS_KEY.to_owned(),
+            secret_key: SECRET_KEY.to_owned(),
+        };
+        (client, mock)
+    }
+
+    #[test]
+    fn signature_matches_a_known_vector() {
+        let signature = sign(
+            "secret-key",
+            "GET",
+            "0123456789abcdef0123456789",
+            "Mon, 07 Oct 2024 12:00:00 GMT",
+            CONTENT_TYPE,
+            "/api/v6/documents",
+            "limit=1",
+        );
+        assert_eq!(signature, "/yOuzZJe225uQ88ZFfJeoTdsGYY6bn/wV9CHAm25rz4=");
+    }
+
+    #[tokio::test]
+    async fn signed_requests_are_accepted() {
+        let (client, _mock) = client_and_mock().await;
+        let document_id = "0123456789abcdef01234567";
+        let document = client.document(document_id).await.unwrap();
+        assert!(document.can_write());
+
+        let workspaces = client.workspaces(document_id).await.unwrap();
+        assert_eq!(workspaces[0].id, document.default_workspace.id);
+    }
+
+    #[tokio::test]
+    async fn wrong_keys_are_rejected() {
+        let (mut client, _mock) = client_and_mock().await;
+        client.secret_key = "wrong-secret".to_owned();
+        let err = client.documents(Some(1)).await.unwrap_err();
+        assert!(matches!(err, OnshapeError::Status { status: 401, .. }));
+    }
+
+    #[tokio::test]
+    async fn documents_are_collected_across_pages() {
+        let (client, mock) = client_and_mock().await;
+        let ids: Vec<String> = (0..45)
+            .map(|index| format!("{:024x}", 1000 + index))
+            .collect();
+        mock.add_documents(&ids);
+
+        let all = client.documents(None).await.unwrap();
+        assert_eq!(all.len(), 45);
+        assert_eq!(mock.statuses(), [200, 200, 200]);
+
+        let some = client.documents(Some(25)).await.unwrap();
+        assert_eq!(some.len(), 25);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_are_retried() {
+        let (client, mock) = client_and_mock().await;
+        mock.rate_limit(2);
+        client.documents(Some(1)).await.unwrap();
+        assert_eq!(mock.statuses(), [429, 429, 200]);
+    }
+
+    #[tokio::test]
+    async fn rate_limiting_gives_up_eventually() {
+        let (client, mock) = client_and_mock().await;
+        mock.rate_limit(usize::MAX);
+        let err = client.documents(Some(1)).await.unwrap_err();
+        assert!(matches!(err, OnshapeError::RateLimited));
+        assert_eq!(mock.statuses().len(), MAX_RATE_LIMIT_RETRIES as usize + 1);
+    }
+
+    fn extrude(operation: &str) -> Value {
+        json!({
+            "btType": "BTMFeature-134",
+            "featureType": "extrude",
+            "name": "Extrude",
+            "parameters": [{
+                "btType": "BTMParameterEnum-145",
+                "parameterId": "operationType",
+                "enumName": "NewBodyOperationType",
+                "value": operation,
+            }],
+        })
+    }
+
+    #[tokio::test]
+    async fn new_extrudes_make_measurable_parts() {
+        let (client, mock) = client_and_mock().await;
+        let document_id = "0123456789abcdef01234567";
+        let workspace_id = client
+            .document(document_id)
+            .await
+            .unwrap()
+            .default_workspace
+            .id;
+        let element_id = client
+            .part_studios(document_id, &workspace_id)
+            .await
+            .unwrap()[0]
+            .id
+            .clone();
+        assert!(client
+            .parts(document_id, &workspace_id, &element_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        mock.add_feature(document_id, &element_id, extrude("NEW"));
+        mock.add_feature(document_id, &element_id, extrude("REMOVE"));
+        let parts = client
+            .parts(document_id, &workspace_id, &element_id)
+            .await
+            .unwrap();
+        assert_eq!(parts.len(), 1);
+        let part_id = parts[0].part_id.as_str();
+
+        let bounds = client
+            .part_bounding_box(document_id, &workspace_id, &element_id, part_id)
+            .await
+            .unwrap();
+        assert_eq!((bounds.low_x, bounds.high_x), (0.0, 0.0254));
+        let volume = client
+            .part_volume(document_id, &workspace_id, &element_id, part_id)
+            .await
+            .unwrap();
+        assert!((volume - 0.0254f64.powi(3)).abs() < 1e-12);
+        let faces = client
+            .part_face_count(document_id, &workspace_id, &element_id, part_id)
+            .await
+            .unwrap();
+        assert_eq!(faces, 6);
+    }
+
+    #[tokio::test]
+    async fn translations_finish_and_download() {
+        let (client, mock) = client_and_mock().await;
+        let document_id = "0123456789abcdef01234567";
+        let workspace_id = client
+            .document(document_id)
+            .await
+            .unwrap()
+            .default_workspace
+            .id;
+        let element_id = client
+            .part_studios(document_id, &workspace_id)
+            .await
+            .unwrap()[0]
+            .id
+            .clone();
+
+        let started = client
+            .start_translation(document_id, &workspace_id, &element_id, "STEP")
+            .await
+            .unwrap();
+        assert_eq!(started.request_state, "ACTIVE");
+        let status = client.translation_status(&started.id).await.unwrap();
+        assert_eq!(status.request_state, "DONE");
+
+        let file_id = &status.result_external_data_ids[0];
+        let url = client.external_data_url(document_id, file_id).unwrap();
+        assert!(url.as_str().starts_with(&mock.api_url()));
+        let file = client
+            .download_external_data(document_id, file_id)
+            .await
+            .unwrap();
+        assert!(String::from_utf8(file)
+            .unwrap()
+            .starts_with("mock STEP export"));
+        assert!(mock
+            .requested()
+            .last()
+            .is_some_and(|request| request.contains("/externaldata/")));
+    }
+}
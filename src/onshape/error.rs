@@ -0,0 +1,84 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum OnshapeError {
+    #[error("request to Onshape failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("Onshape responded with {status}: {message}")]
+    Status { status: u16, message: String },
+
+    #[error("Onshape is rate limiting requests; try again later")]
+    RateLimited,
+
+    #[error("unexpected Onshape response: missing {0}")]
+    Malformed(&'static str),
+
+    #[error("{0}")]
+    InvalidTarget(String),
+
+    #[error("document {0} does not exist")]
+    DocumentNotFound(String),
+
+    #[error("your Onshape keys can't access document {0}")]
+    NoAccess(String),
+
+    #[error("your Onshape keys can't edit document {0}")]
+    ReadOnly(String),
+
+    #[error("document {0} has no part studio to build in")]
+    NoPartStudio(String),
+
+    #[error("translation failed: {0}")]
+    TranslationFailed(String),
+}
+
+impl OnshapeError {
+    /// A short, stable name for the error, for clients to match on
+    pub fn reason(&self) -> &'static str {
+        match self {
+            OnshapeError::Request(_) => "unreachable",
+            OnshapeError::Status { status: 401, .. } => "unauthorized",
+            OnshapeError::Status { status: 403, .. } => "forbidden",
+            OnshapeError::Status { status, .. } if *status >= 500 => "onshape_unavailable",
+            OnshapeError::Status { .. } => "request_rejected",
+            OnshapeError::RateLimited => "rate_limited",
+            OnshapeError::Malformed(_) => "malformed_response",
+            OnshapeError::InvalidTarget(_) => "invalid_target",
+            OnshapeError::DocumentNotFound(_) => "not_found",
+            OnshapeError::NoAccess(_) => "no_access",
+            OnshapeError::ReadOnly(_) => "read_only",
+            OnshapeError::NoPartStudio(_) => "no_part_studio",
+            OnshapeError::TranslationFailed(_) => "translation_failed",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16) -> OnshapeError {
+        OnshapeError::Status {
+            status,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn statuses_have_distinct_reasons() {
+        assert_eq!(status(401).reason(), "unauthorized");
+        assert_eq!(status(403).reason(), "forbidden");
+        assert_eq!(status(500).reason(), "onshape_unavailable");
+        assert_eq!(status(503).reason(), "onshape_unavailable");
+        assert_eq!(status(400).reason(), "request_rejected");
+        assert_eq!(OnshapeError::RateLimited.reason(), "rate_limited");
+    }
+
+    #[test]
+    fn inaccessible_documents_are_not_missing_ones() {
+        let id = "0123456789abcdef01234567".to_owned();
+        assert_eq!(OnshapeError::NoAccess(id.clone()).reason(), "no_access");
+        assert_eq!(OnshapeError::DocumentNotFound(id).reason(), "not_found");
+    }
+}
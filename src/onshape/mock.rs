@@ -0,0 +1,863 @@
+//! A local stand-in for the parts of Onshape's REST API the server and OnPy
+//! use, so whole sessions can run without reaching cad.onshape.com. Every
+//! document id is accepted and starts with one empty part studio. Each
+//! extrude or loft that makes a new part adds a one inch cube with six faces.
+//!
+//! It also stands in for the OpenAI API, answering chat completions from
+//! scripted replies, and serves the OnPy guide.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::onshape::client::sign;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8089";
+/// How many documents a page holds when the request doesn't say
+const DEFAULT_PAGE_SIZE: usize = 20;
+/// The side of every mock part, in meters
+const PART_SIZE: f64 = 0.0254;
+/// How much larger than the part an imprecise bounding box is, on each side
+const APPROXIMATE_MARGIN: f64 = 0.1 * PART_SIZE;
+const GUIDE_PATH: &str = "/onpy/guide.md";
+const GUIDE: &str = "# OnPy\n\nThe mock OnPy guide. Sketch on `partstudio.features.top_plane`, then extrude the sketch's faces.\n";
+
+/// A request the mock received, as listed by `GET /__mock/requests`
+#[derive(Serialize, Debug, Clone)]
+struct RecordedRequest {
+    method: String,
+    path: String,
+    query: String,
+    body: Option<Value>,
+    status: u16,
+}
+
+struct MockPartStudio {
+    id: String,
+    name: String,
+    features: Vec<Value>,
+}
+
+struct MockDocument {
+    workspace_id: String,
+    part_studios: Vec<MockPartStudio>,
+    versions: Vec<Value>,
+}
+
+/// A translation, which finishes as soon as its status is asked for
+struct MockTranslation {
+    document_id: String,
+    file_id: String,
+}
+
+struct MockState {
+    access_key: String,
+    secret_key: String,
+    documents: HashMap<String, MockDocument>,
+    next_id: usize,
+    requests: Vec<RecordedRequest>,
+    /// How many of the next requests are answered with 429 Too Many Requests
+    rate_limited: usize,
+    translations: HashMap<String, MockTranslation>,
+    /// Translated files, by external data id
+    files: HashMap<String, Vec<u8>>,
+    /// Chat completion replies, each given when its text is in the prompt
+    llm_replies: Vec<(String, String)>,
+}
+
+impl MockState {
+    fn new(access_key: String, secret_key: String) -> MockState {
+        MockState {
+            access_key,
+            secret_key,
+            documents: HashMap::new(),
+            next_id: 0,
+            requests: Vec::new(),
+            rate_limited: 0,
+            translations: HashMap::new(),
+            files: HashMap::new(),
+            llm_replies: Vec::new(),
+        }
+    }
+
+    /// A new id shaped like Onshape's 24 character hex ids
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{:024x}", self.next_id)
+    }
+
+    fn document(&mut self, document_id: &str) -> &mut MockDocument {
+        if !self.documents.contains_key(document_id) {
+            let workspace_id = self.new_id();
+            let part_studio = MockPartStudio {
+                id: self.new_id(),
+                name: "Part Studio 1".to_owned(),
+                features: Vec::new(),
+            };
+            self.documents.insert(
+                document_id.to_owned(),
+                MockDocument {
+                    workspace_id,
+                    part_studios: vec![part_studio],
+                    versions: Vec::new(),
+                },
+            );
+        }
+        self.documents
+            .get_mut(document_id)
+            .expect("the document was just inserted")
+    }
+}
+
+struct MockRequest {
+    method: String,
+    path: String,
+    query: String,
+    headers: HashMap<String, String>,
+    body: Option<Value>,
+}
+
+impl MockRequest {
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(&name.to_lowercase())
+            .map(String::as_str)
+            .unwrap_or("")
+    }
+
+    /// A query parameter's value, if it was given
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Whether a request carries the mock's keys, as basic auth or as an
+/// Onshape HMAC signature
+fn is_authorized(state: &MockState, request: &MockRequest) -> bool {
+    let authorization = request.header("Authorization");
+
+    if let Some(encoded) = authorization.strip_prefix("Basic ") {
+        let expected = format!("{}:{}", state.access_key, state.secret_key);
+        return BASE64_STANDARD
+            .decode(encoded.trim())
+            .is_ok_and(|decoded| decoded == expected.as_bytes());
+    }
+
+    if let Some(signed) = authorization.strip_prefix("On ") {
+        let Some((access_key, signature)) = signed.split_once(":HmacSHA256:") else {
+            return false;
+        };
+        let expected = sign(
+            &state.secret_key,
+            &request.method,
+            request.header("On-Nonce"),
+            request.header("Date"),
+            request.header("Content-Type"),
+            &request.path,
+            &request.query,
+        );
+        return access_key == state.access_key && signature == expected;
+    }
+
+    false
+}
+
+fn not_found(request: &MockRequest) -> (u16, Value) {
+    (
+        404,
+        json!({ "message": format!("no mock for {} {}", request.method, request.path) }),
+    )
+}
+
+/// What the mock answers with
+struct MockResponse {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    fn json((status, body): (u16, Value)) -> MockResponse {
+        MockResponse {
+            status,
+            content_type: "application/json",
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn bytes(content_type: &'static str, body: Vec<u8>) -> MockResponse {
+        MockResponse {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+}
+
+/// Splits a path into segments, with or without the /api/v6 prefix
+fn segments(path: &str) -> Vec<&str> {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match segments.as_slice() {
+        ["api", version, rest @ ..] if version.starts_with('v') => rest.to_vec(),
+        _ => segments,
+    }
+}
+
+/// Whether a feature makes a new part: extrudes and lofts do, unless they
+/// add to or remove from another part
+fn makes_part(feature: &Value) -> bool {
+    if !matches!(feature["featureType"].as_str(), Some("extrude" | "loft")) {
+        return false;
+    }
+    let operation = feature["parameters"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|parameter| parameter["parameterId"] == "operationType")
+        .and_then(|parameter| parameter["value"].as_str());
+    matches!(operation, None | Some("NEW"))
+}
+
+/// The parts a part studio's features make, in order
+fn part_list(part_studio: &MockPartStudio) -> Vec<Value> {
+    part_studio
+        .features
+        .iter()
+        .filter(|feature| makes_part(feature))
+        .enumerate()
+        .map(|(index, _)| {
+            json!({
+                "partId": format!("J{}", index + 1),
+                "name": format!("Part {}", index + 1),
+            })
+        })
+        .collect()
+}
+
+fn bounding_box_json(precise: bool) -> Value {
+    let margin = if precise { 0.0 } else { APPROXIMATE_MARGIN };
+    json!({
+        "lowX": -margin,
+        "lowY": -margin,
+        "lowZ": -margin,
+        "highX": PART_SIZE + margin,
+        "highY": PART_SIZE + margin,
+        "highZ": PART_SIZE + margin,
+    })
+}
+
+/// Answers the OpenAI API's model list and chat completions. Any API key is
+/// accepted.
+fn openai(state: &MockState, request: &MockRequest) -> (u16, Value) {
+    match (request.method.as_str(), segments(&request.path).as_slice()) {
+        ("GET", ["v1", "models"]) => (
+            200,
+            json!({
+                "object": "list",
+                "data": [{ "id": "gpt-4o", "object": "model", "created": 0, "owned_by": "mock" }],
+            }),
+        ),
+        ("POST", ["v1", "chat", "completions"]) => {
+            let prompt = request
+                .body
+                .as_ref()
+                .and_then(|body| body["messages"].as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|message| message["content"].as_str())
+                .collect::<Vec<&str>>()
+                .join("\n");
+            let Some((_, reply)) = state
+                .llm_replies
+                .iter()
+                .find(|(needle, _)| prompt.contains(needle.as_str()))
+            else {
+                return (
+                    400,
+                    json!({
+                        "error": {
+                            "message": "the mock has no reply scripted for this prompt",
+                            "type": "invalid_request_error",
+                        }
+                    }),
+                );
+            };
+            (
+                200,
+                json!({
+                    "id": "chatcmpl-mock",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": request.body.as_ref().map(|body| body["model"].clone()),
+                    "choices": [{
+                        "index": 0,
+                        "message": { "role": "assistant", "content": reply },
+                        "finish_reason": "stop",
+                    }],
+                    "usage": { "prompt_tokens": 0, "completion_tokens": 0, "total_tokens": 0 },
+                }),
+            )
+        }
+        _ => not_found(request),
+    }
+}
+
+fn element_json(part_studio: &MockPartStudio) -> Value {
+    json!({
+        "id": part_studio.id,
+        "name": part_studio.name,
+        "elementType": "PARTSTUDIO",
+    })
+}
+
+/// Answers one request, returning its status and body
+fn route(state: &mut MockState, request: &MockRequest) -> (u16, Value) {
+    match (request.method.as_str(), segments(&request.path).as_slice()) {
+        ("GET", ["documents"]) => {
+            let limit = request
+                .param("limit")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(DEFAULT_PAGE_SIZE)
+                .max(1);
+            let offset: usize = request
+                .param("offset")
+                .and_then(|offset| offset.parse().ok())
+                .unwrap_or(0);
+
+            let mut ids: Vec<String> = state.documents.keys().cloned().collect();
+            ids.sort();
+            let items: Vec<Value> = ids
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .map(|id| document_json(state, id))
+                .collect();
+            let next = (offset + limit < ids.len()).then(|| {
+                format!(
+                    "http://{}{}?offset={}&limit={}",
+                    request.header("Host"),
+                    request.path,
+                    offset + limit,
+                    limit
+                )
+            });
+            (200, json!({ "items": items, "next": next }))
+        }
+        ("GET", ["documents", document_id]) => (200, document_json(state, document_id)),
+        ("GET", ["documents", "d", document_id, "workspaces"]) => {
+            let workspace_id = state.document(document_id).workspace_id.clone();
+            (200, json!([{ "id": workspace_id, "name": "Main" }]))
+        }
+        ("GET", ["documents", "d", document_id, "w", workspace_id, "elements"]) => {
+            let document = state.document(document_id);
+            if &document.workspace_id != workspace_id {
+                return not_found(request);
+            }
+            let elements: Vec<Value> = document.part_studios.iter().map(element_json).collect();
+            (200, json!(elements))
+        }
+        ("POST", ["documents", "d", document_id, "versions"]) => {
+            let id = state.new_id();
+            let name = request
+                .body
+                .as_ref()
+                .and_then(|body| body["name"].as_str())
+                .unwrap_or("Version")
+                .to_owned();
+            let version = json!({ "id": id, "name": name });
+            state.document(document_id).versions.push(version.clone());
+            (200, version)
+        }
+        ("POST", ["partstudios", "d", document_id, "w", _]) => {
+            let id = state.new_id();
+            let name = request
+                .body
+                .as_ref()
+                .and_then(|body| body["name"].as_str())
+                .unwrap_or("Part Studio")
+                .to_owned();
+            let part_studio = MockPartStudio {
+                id,
+                name,
+                features: Vec::new(),
+            };
+            let element = element_json(&part_studio);
+            state.document(document_id).part_studios.push(part_studio);
+            (200, element)
+        }
+        ("POST", ["partstudios", "d", document_id, "w", _, "e", element_id, "translations"]) => {
+            let format_name = request
+                .body
+                .as_ref()
+                .and_then(|body| body["formatName"].as_str())
+                .unwrap_or("STEP")
+                .to_owned();
+            let has_part_studio = state
+                .document(document_id)
+                .part_studios
+                .iter()
+                .any(|part_studio| &part_studio.id == element_id);
+            if !has_part_studio {
+                return not_found(request);
+            }
+
+            let (id, file_id) = (state.new_id(), state.new_id());
+            let contents = format!(
+                "mock {} export of part studio {}\n",
+                format_name, element_id
+            );
+            state.files.insert(file_id.clone(), contents.into_bytes());
+            state.translations.insert(
+                id.clone(),
+                MockTranslation {
+                    document_id: document_id.to_string(),
+                    file_id,
+                },
+            );
+            (200, json!({ "id": id, "requestState": "ACTIVE" }))
+        }
+        ("GET", ["translations", translation_id]) => {
+            match state.translations.get(*translation_id) {
+                Some(translation) => (
+                    200,
+                    json!({
+                        "id": translation_id,
+                        "requestState": "DONE",
+                        "resultDocumentId": translation.document_id,
+                        "resultExternalDataIds": [translation.file_id],
+                    }),
+                ),
+                None => not_found(request),
+            }
+        }
+        (
+            method,
+            ["partstudios", "d", document_id, "w", _, "e", element_id, "features", rest @ ..],
+        ) => {
+            // Reserved before the document is borrowed, for a new feature
+            let feature_id = (method == "POST").then(|| state.new_id());
+            let document = state.document(document_id);
+            let Some(part_studio) = document
+                .part_studios
+                .iter_mut()
+                .find(|part_studio| &part_studio.id == element_id)
+            else {
+                return not_found(request);
+            };
+
+            match (method, rest) {
+                ("GET", []) => (200, json!({ "features": part_studio.features })),
+                ("POST", []) => {
+                    let mut feature = request
+                        .body
+                        .as_ref()
+                        .map(|body| body["feature"].clone())
+                        .unwrap_or(Value::Null);
+                    if !feature.is_object() {
+                        return (400, json!({ "message": "missing feature" }));
+                    }
+                    feature["featureId"] = json!(feature_id);
+                    part_studio.features.push(feature.clone());
+                    (
+                        200,
+                        json!({
+                            "feature": feature,
+                            "featureState": { "featureStatus": "OK" },
+                        }),
+                    )
+                }
+                ("DELETE", ["featureid", feature_id]) => {
+                    let count = part_studio.features.len();
+                    part_studio
+                        .features
+                        .retain(|feature| feature["featureId"].as_str() != Some(feature_id));
+                    if part_studio.features.len() == count {
+                        return not_found(request);
+                    }
+                    (200, json!({}))
+                }
+                _ => not_found(request),
+            }
+        }
+        ("GET", ["parts", "d", document_id, "w", _, "e", element_id, rest @ ..]) => {
+            let document = state.document(document_id);
+            let Some(part_studio) = document
+                .part_studios
+                .iter()
+                .find(|part_studio| &part_studio.id == element_id)
+            else {
+                return not_found(request);
+            };
+            let parts = part_list(part_studio);
+
+            let (part_id, property) = match rest {
+                [] => return (200, json!(parts)),
+                ["partid", part_id, property] => (*part_id, *property),
+                _ => return not_found(request),
+            };
+            if !parts.iter().any(|part| part["partId"] == part_id) {
+                return not_found(request);
+            }
+
+            match property {
+                "boundingboxes" => (
+                    200,
+                    bounding_box_json(request.param("precise") == Some("true")),
+                ),
+                "massproperties" => {
+                    // The volume, then its lower and upper bounds
+                    let volume = PART_SIZE.powi(3);
+                    (
+                        200,
+                        json!({ "bodies": { part_id: { "volume": [volume, volume, volume] } } }),
+                    )
+                }
+                "bodydetails" => {
+                    let faces: Vec<Value> = (1..=6)
+                        .map(|face| json!({ "id": format!("F{}", face) }))
+                        .collect();
+                    (
+                        200,
+                        json!({ "bodies": [{ "id": part_id, "faces": faces }] }),
+                    )
+                }
+                _ => not_found(request),
+            }
+        }
+        _ => not_found(request),
+    }
+}
+
+/// A translated file, as raw bytes rather than JSON
+fn external_data(state: &MockState, request: &MockRequest) -> Option<MockResponse> {
+    match (request.method.as_str(), segments(&request.path).as_slice()) {
+        ("GET", ["documents", "d", _, "externaldata", file_id]) => {
+            Some(match state.files.get(*file_id) {
+                Some(contents) => MockResponse::bytes("application/octet-stream", contents.clone()),
+                None => MockResponse::json(not_found(request)),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Answers one request, from the OpenAI stand-in, the guide, or the
+/// Onshape API
+fn respond(state: &mut MockState, request: &MockRequest) -> MockResponse {
+    if request.path.starts_with("/v1/") {
+        return MockResponse::json(openai(state, request));
+    }
+    if request.method == "GET" && request.path == GUIDE_PATH {
+        return MockResponse::bytes("text/markdown", GUIDE.as_bytes().to_vec());
+    }
+
+    if state.rate_limited > 0 {
+        state.rate_limited -= 1;
+        return MockResponse::json((429, json!({ "message": "too many requests" })));
+    }
+    if !is_authorized(state, request) {
+        return MockResponse::json((401, json!({ "message": "unauthorized" })));
+    }
+    external_data(state, request).unwrap_or_else(|| MockResponse::json(route(state, request)))
+}
+
+fn document_json(state: &mut MockState, document_id: &str) -> Value {
+    let workspace_id = state.document(document_id).workspace_id.clone();
+    json!({
+        "id": document_id,
+        "name": "Mock document",
+        "defaultWorkspace": { "id": workspace_id, "name": "Main" },
+        "permissionSet": ["READ", "WRITE", "DELETE"],
+    })
+}
+
+/// Reads one HTTP request from the connection. Returns `None` once the
+/// client has closed it.
+async fn read_request(stream: &mut BufReader<TcpStream>) -> io::Result<Option<MockRequest>> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad request line",
+        ));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (method, path, query) = (method.to_owned(), path.to_owned(), query.to_owned());
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+        }
+    }
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+
+    Ok(Some(MockRequest {
+        method,
+        path,
+        query,
+        headers,
+        body: serde_json::from_slice(&body).ok(),
+    }))
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<Mutex<MockState>>) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    while let Some(request) = read_request(&mut stream).await? {
+        let response = {
+            let mut state = state.lock().expect("mock state lock poisoned");
+            if request.method == "GET" && request.path == "/__mock/requests" {
+                MockResponse::json((200, json!(state.requests)))
+            } else {
+                let response = respond(&mut state, &request);
+                state.requests.push(RecordedRequest {
+                    method: request.method.clone(),
+                    path: request.path.clone(),
+                    query: request.query.clone(),
+                    body: request.body.clone(),
+                    status: response.status,
+                });
+                response
+            }
+        };
+        println!(
+            "mock onshape: {} {} -> {}",
+            request.method, request.path, response.status
+        );
+
+        let retry_after = if response.status == 429 {
+            "Retry-After: 0\r\n"
+        } else {
+            ""
+        };
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\n\r\n",
+            response.status,
+            if response.status < 300 { "OK" } else { "Error" },
+            response.content_type,
+            retry_after,
+            response.body.len(),
+        );
+        stream.get_mut().write_all(head.as_bytes()).await?;
+        stream.get_mut().write_all(&response.body).await?;
+    }
+    Ok(())
+}
+
+/// A mock Onshape server running in the background
+pub struct MockServer {
+    address: SocketAddr,
+    /// Shared with the connections; only tests look at it directly
+    #[cfg_attr(not(test), allow(dead_code))]
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+    /// Starts serving on `address`, accepting the given keys
+    pub async fn start(
+        address: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> io::Result<MockServer> {
+        let state = Arc::new(Mutex::new(MockState::new(
+            access_key.to_owned(),
+            secret_key.to_owned(),
+        )));
+        let listener = TcpListener::bind(address).await?;
+        let address = listener.local_addr()?;
+
+        let accepted_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let socket = match listener.accept().await {
+                    Ok((socket, _)) => socket,
+                    Err(err) => {
+                        eprintln!("mock onshape stopped accepting connections: {}", err);
+                        return;
+                    }
+                };
+                let state = accepted_state.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(socket, state).await {
+                        eprintln!("mock onshape connection failed: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(MockServer { address, state })
+    }
+
+    /// The URL to point ONSHAPE_API_URL at
+    pub fn api_url(&self) -> String {
+        format!("http://{}/api/v6", self.address)
+    }
+
+    /// The URL to point OPENAI_API_BASE_URL at
+    pub fn openai_url(&self) -> String {
+        format!("http://{}/v1", self.address)
+    }
+
+    /// The URL to point ONPY_GUIDE_URL at
+    pub fn guide_url(&self) -> String {
+        format!("http://{}{}", self.address, GUIDE_PATH)
+    }
+}
+
+#[cfg(test)]
+impl MockServer {
+    /// Adds documents with the given ids
+    pub fn add_documents(&self, document_ids: &[String]) {
+        let mut state = self.state.lock().unwrap();
+        for document_id in document_ids {
+            state.document(document_id);
+        }
+    }
+
+    /// Answers the next `count` requests with 429 Too Many Requests
+    pub fn rate_limit(&self, count: usize) {
+        self.state.lock().unwrap().rate_limited = count;
+    }
+
+    /// Adds a feature to a part studio, as OnPy would
+    pub fn add_feature(&self, document_id: &str, element_id: &str, feature: Value) {
+        let mut state = self.state.lock().unwrap();
+        let feature_id = state.new_id();
+        let part_studio = state
+            .document(document_id)
+            .part_studios
+            .iter_mut()
+            .find(|part_studio| part_studio.id == element_id)
+            .expect("no such part studio");
+        let mut feature = feature;
+        feature["featureId"] = json!(feature_id);
+        part_studio.features.push(feature);
+    }
+
+    /// Answers chat completions whose prompt contains `needle` with `reply`
+    pub fn reply_to(&self, needle: &str, reply: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .llm_replies
+            .push((needle.to_owned(), reply.to_owned()));
+    }
+
+    /// The method and path of every request received, in order
+    pub fn requested(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .map(|request| format!("{} {}", request.method, request.path))
+            .collect()
+    }
+
+    /// The status of every request received, in order
+    pub fn statuses(&self) -> Vec<u16> {
+        let state = self.state.lock().unwrap();
+        state
+            .requests
+            .iter()
+            .map(|request| request.status)
+            .collect()
+    }
+}
+
+/// Runs the mock Onshape server until the process is stopped. It accepts the
+/// keys in ONSHAPE_DEV_ACCESS and ONSHAPE_DEV_SECRET, defaulting to
+/// "mock-access" and "mock-secret".
+///
+/// Usage: `polybrain-core --mock-onshape [address]`
+///
+/// Point the server at it with `ONSHAPE_API_URL=http://<address>/api/v6`,
+/// `OPENAI_API_BASE_URL=http://<address>/v1` and
+/// `ONPY_GUIDE_URL=http://<address>/onpy/guide.md`. No chat replies are
+/// scripted when it runs this way, so completions are answered with errors.
+/// `GET /__mock/requests` lists every request the mock has received.
+pub async fn run_mock_server(args: &[String]) -> io::Result<()> {
+    let address = args.first().map(String::as_str).unwrap_or(DEFAULT_ADDRESS);
+    let access_key =
+        std::env::var("ONSHAPE_DEV_ACCESS").unwrap_or_else(|_| "mock-access".to_owned());
+    let secret_key =
+        std::env::var("ONSHAPE_DEV_SECRET").unwrap_or_else(|_| "mock-secret".to_owned());
+
+    let server = MockServer::start(address, &access_key, &secret_key).await?;
+    println!("mock onshape listening on {}", server.api_url());
+
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn chat_completions_come_from_scripted_replies() {
+        let mock = MockServer::start("127.0.0.1:0", "access", "secret")
+            .await
+            .unwrap();
+        mock.reply_to("Classify the response", "decision: accept");
+        let client = reqwest::Client::new();
+
+        let models = client
+            .get(format!("{}/models", mock.openai_url()))
+            .bearer_auth("any-token")
+            .send()
+            .await
+            .unwrap();
+        assert!(models.status().is_success());
+
+        let completion = |prompt: &str| {
+            client
+                .post(format!("{}/chat/completions", mock.openai_url()))
+                .json(&json!({
+                    "model": "gpt-4o",
+                    "messages": [{ "role": "user", "content": prompt }],
+                }))
+                .send()
+        };
+        let answered: Value = completion("Classify the response. They said yes")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            answered["choices"][0]["message"]["content"],
+            "decision: accept"
+        );
+        let unscripted = completion("Something else").await.unwrap();
+        assert_eq!(unscripted.status(), 400);
+    }
+}
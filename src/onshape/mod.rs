@@ -0,0 +1,9 @@
+//! A typed client for the parts of Onshape's REST API the server uses
+
+mod client;
+mod error;
+pub mod mock;
+pub mod types;
+
+pub use client::OnshapeClient;
+pub use error::OnshapeError;
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// One page of a paginated list
+#[derive(Deserialize, Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The URL of the next page, if there is one
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    pub default_workspace: Workspace,
+    /// Such as READ, WRITE, or DELETE
+    #[serde(default)]
+    pub permission_set: Vec<String>,
+}
+
+impl Document {
+    pub fn can_write(&self) -> bool {
+        self.permission_set
+            .iter()
+            .any(|permission| permission == "WRITE")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Workspace {
+    pub id: String,
+}
+
+/// An element of a document, such as a part studio
+#[derive(Deserialize, Debug, Clone)]
+pub struct ElementInfo {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Version {
+    pub id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureInfo {
+    pub feature_id: String,
+    pub name: String,
+    pub feature_type: String,
+    #[serde(default)]
+    pub suppressed: bool,
+}
+
+/// The features of a part studio, in order
+#[derive(Deserialize, Debug, Clone)]
+pub struct FeatureList {
+    pub features: Vec<FeatureInfo>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PartInfo {
+    pub part_id: String,
+    pub name: String,
+}
+
+/// Corners of a bounding box, in meters
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BoundingBoxInfo {
+    pub low_x: f64,
+    pub low_y: f64,
+    pub low_z: f64,
+    pub high_x: f64,
+    pub high_y: f64,
+    pub high_z: f64,
+}
+
+/// Mass properties, keyed by part id
+#[derive(Deserialize, Debug, Clone)]
+pub struct MassProperties {
+    pub bodies: HashMap<String, BodyMassProperties>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BodyMassProperties {
+    /// The volume, then its lower and upper bounds, in cubic meters
+    pub volume: Vec<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BodyDetails {
+    pub bodies: Vec<BodyDetail>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BodyDetail {
+    #[serde(default)]
+    pub faces: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslationStatus {
+    pub id: String,
+    /// One of ACTIVE, DONE, or FAILED
+    pub request_state: String,
+    #[serde(default)]
+    pub result_external_data_ids: Vec<String>,
+    #[serde(default)]
+    pub failure_reason: Option<String>,
+}
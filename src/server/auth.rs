@@ -1,10 +1,13 @@
 use std::error::Error;
 
-use crate::server::types::{ONSHAPE_API, OPENAI_API};
+use crate::chain::checkpoint::{Checkpoint, CheckpointError, CheckpointStore};
+use crate::onshape::{OnshapeClient, OnshapeError};
+use crate::server::types::openai_api;
 
 use super::types::{ApiCredentials, UserDocument, UserInfo};
 
 use aes::Aes128;
+use async_trait::async_trait;
 use block_modes::block_padding::Pkcs7;
 use block_modes::BlockMode;
 use block_modes::Cbc;
@@ -12,7 +15,7 @@ use hex::decode;
 use log::{info, warn};
 use mongodb::{
     bson::doc,
-    options::{ClientOptions, ServerApi, ServerApiVersion},
+    options::{ClientOptions, ReplaceOptions, ServerApi, ServerApiVersion},
     Client, Collection,
 };
 use sha2::{Digest, Sha256};
@@ -21,6 +24,7 @@ use sha2::{Digest, Sha256};
 pub struct MongoUtil {
     mongo_client: Client,
     user_collection: Collection<UserDocument>,
+    checkpoint_collection: Collection<Checkpoint>,
 }
 
 type Aes128Cbc = Cbc<Aes128, Pkcs7>;
@@ -63,17 +67,18 @@ impl MongoUtil {
 
         // Get a handle to the cluster
         let mongo_client = Client::with_options(client_options)?;
-        let user_collection = mongo_client
-            .database(
-                std::env::var("MONGODB_DATABASE")
-                    .expect("MONGODB_DATABASE must be set")
-                    .as_str(),
-            )
-            .collection("users");
+        let database = mongo_client.database(
+            std::env::var("MONGODB_DATABASE")
+                .expect("MONGODB_DATABASE must be set")
+                .as_str(),
+        );
+        let user_collection = database.collection("users");
+        let checkpoint_collection = database.collection("checkpoints");
 
         let new_instance = MongoUtil {
             mongo_client,
             user_collection,
+            checkpoint_collection,
         };
 
         new_instance.ping().await.expect("MongoDB ping failed");
@@ -104,6 +109,27 @@ impl MongoUtil {
     }
 }
 
+#[async_trait]
+impl CheckpointStore for MongoUtil {
+    async fn save(&self, checkpoint: &Checkpoint) -> Result<(), CheckpointError> {
+        let filter = doc! { "session_id": &checkpoint.session_id };
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.checkpoint_collection
+            .replace_one(filter, checkpoint, options)
+            .await
+            .map_err(|err| CheckpointError::Store(err.to_string()))?;
+        Ok(())
+    }
+
+    async fn load(&self, session_id: &str) -> Result<Option<Checkpoint>, CheckpointError> {
+        let filter = doc! { "session_id": session_id };
+        self.checkpoint_collection
+            .find_one(filter, None)
+            .await
+            .map_err(|err| CheckpointError::Store(err.to_string()))
+    }
+}
+
 pub async fn fetch_user_id(user_token: &str) -> Result<String, Box<dyn Error>> {
     let client = reqwest::Client::new();
     println!("fetching user id...");
@@ -131,25 +157,11 @@ async fn validate_credentials(credentials: &ApiCredentials) -> Result<(), String
     println!("validating credentials...");
 
     println!("pinging onshape...");
-    let client = reqwest::Client::new();
-    let response = client
-        .get(format!("{}/documents?limit=1", ONSHAPE_API))
-        .basic_auth(
-            &credentials.onshape_access_key,
-            Some(&credentials.onshape_secret_key),
-        )
-        .send()
-        .await;
-
-    match response {
-        Ok(r) => {
-            if r.status().is_success() {
-                println!("OnShape ping successful");
-            } else {
-                let response_message = r.text().await.unwrap();
-                println!("OnShape responded with error: \n{}", response_message);
-                return Err("Bad OnShape Credentials".to_string());
-            }
+    match OnshapeClient::new(credentials).documents(Some(1)).await {
+        Ok(_) => println!("OnShape ping successful"),
+        Err(err @ OnshapeError::Status { .. }) => {
+            println!("OnShape responded with error: \n{}", err);
+            return Err("Bad OnShape Credentials".to_string());
         }
         Err(err) => {
             println!("Call to onshape had internal error: {}", err);
@@ -158,8 +170,9 @@ async fn validate_credentials(credentials: &ApiCredentials) -> Result<(), String
     }
 
     println!("pinging open ai...");
+    let client = reqwest::Client::new();
     let response = client
-        .get(format!("{}/models", OPENAI_API))
+        .get(format!("{}/models", openai_api()))
         .bearer_auth(&credentials.openai_token)
         .send()
         .await;
@@ -184,7 +197,9 @@ async fn validate_credentials(credentials: &ApiCredentials) -> Result<(), String
     Ok(())
 }
 
-pub async fn fetch_user_credentials(user_token: &str) -> Result<ApiCredentials, String> {
+/// Resolves the user behind a session token, returning their id and
+/// validated API credentials
+pub async fn fetch_user_credentials(user_token: &str) -> Result<(String, ApiCredentials), String> {
     // TODO: make a global, mutex-protected instance to avoid having to reconnect for each connection
     let mongo_instance = MongoUtil::new()
         .await
@@ -231,5 +246,5 @@ pub async fn fetch_user_credentials(user_token: &str) -> Result<ApiCredentials,
     println!("Got credentials: {:?}", credentials);
     validate_credentials(&credentials).await?;
 
-    Ok(credentials)
+    Ok((user_id, credentials))
 }
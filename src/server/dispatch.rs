@@ -1,48 +1,82 @@
 use crate::{
-    chain::chain_entry::enter_chain,
+    chain::{
+        chain_entry::enter_chain,
+        checkpoint::{CheckpointStore, Checkpointer, SessionState},
+        onshape::{check_document, resolve_part_studio, BuildMode},
+        pipeline::ChainConfig,
+        session_io::{SessionIo, SessionTransport},
+    },
     server::{
-        auth::fetch_user_credentials,
+        auth::{fetch_user_credentials, MongoUtil},
         codec::{send_error, send_message, wait_for_message},
-        error::{AuthenticationError, InternalError},
+        error::{AuthenticationError, DocumentError, InternalError, RequestError},
         types::{ApiCredentials, SessionStartResponse, UserPromptInitial},
     },
 };
-use std::error::Error;
+use async_trait::async_trait;
+use std::{error::Error, sync::Arc};
 use tokio::{io::AsyncWriteExt, net::TcpStream, sync::Mutex};
 use tokio_tungstenite::{accept_async, WebSocketStream};
 
 use uuid::Uuid;
 
-use super::types::{ServerResponse, ServerResponseType, SessionStartRequest, UserInputResponse};
+use super::types::{ServerResponse, SessionStartRequest, UserInputResponse};
 
-async fn query_input_callback(
-    ws_mutex: &Mutex<WebSocketStream<&mut TcpStream>>,
-    input: String,
-) -> Result<String, Box<dyn Error>> {
-    let mut ws_stream = ws_mutex.lock().await;
+/// Carries session IO over the client's websocket
+struct WebSocketTransport<'a> {
+    ws_stream: Mutex<WebSocketStream<&'a mut TcpStream>>,
+}
 
-    send_message(
-        &mut ws_stream,
-        ServerResponse {
-            response_type: ServerResponseType::Query,
-            content: input.to_owned(),
-        },
-    )
-    .await?;
+#[async_trait]
+impl SessionTransport for WebSocketTransport<'_> {
+    async fn ask(&self, query: ServerResponse) -> Result<UserInputResponse, Box<dyn Error>> {
+        let mut ws_stream = self.ws_stream.lock().await;
 
-    let incoming: UserInputResponse = wait_for_message(&mut ws_stream).await?;
-    Ok(incoming.response)
-}
+        send_message(&mut ws_stream, query).await?;
 
-async fn send_output_callback(
-    ws_mutex: &Mutex<WebSocketStream<&mut TcpStream>>,
-    output: ServerResponse,
-) -> Result<(), Box<dyn Error>> {
-    let mut ws_stream = ws_mutex.lock().await;
+        let incoming: UserInputResponse = wait_for_message(&mut ws_stream).await?;
+        Ok(incoming)
+    }
 
-    send_message(&mut ws_stream, output).await?;
+    async fn send(&self, message: ServerResponse) -> Result<(), Box<dyn Error>> {
+        let mut ws_stream = self.ws_stream.lock().await;
 
-    Ok(())
+        send_message(&mut ws_stream, message).await?;
+
+        Ok(())
+    }
+}
+
+/// The chain options a new session asked for
+fn config_from_request(incoming: &SessionStartRequest) -> ChainConfig {
+    let mut config = ChainConfig::default();
+    if let Some(stages) = &incoming.pipeline {
+        config.stages = stages.clone();
+    }
+    if let Some(display_unit) = incoming.display_unit {
+        config.display_unit = display_unit;
+    }
+    if let Some(max_turns) = incoming.pessimist_max_turns {
+        config.pessimist_max_turns = max_turns;
+    }
+    if incoming.approve_outline {
+        config.add_outline_approval();
+    }
+    config.build_mode = if incoming.edit_existing {
+        BuildMode::EditExisting
+    } else if incoming.allow_wipe {
+        BuildMode::Wipe
+    } else {
+        BuildMode::Isolated
+    };
+    config.part_studio = incoming.part_studio.clone();
+    if incoming.edit_existing {
+        config.edit_existing();
+    }
+    if let Some(export_formats) = &incoming.export_formats {
+        config.export_formats = export_formats.clone();
+    }
+    config
 }
 
 async fn start_execution_loop(
@@ -53,36 +87,156 @@ async fn start_execution_loop(
     println!("waiting for incoming message...");
     let incoming: SessionStartRequest = wait_for_message(&mut ws_stream).await?;
 
-    let credentials: ApiCredentials = match fetch_user_credentials(&incoming.user_token).await {
-        Ok(c) => c,
-        Err(message) => {
-            send_error(&mut ws_stream, AuthenticationError { message }).await?;
-            return Ok(());
-        }
+    let (user_id, credentials): (String, ApiCredentials) =
+        match fetch_user_credentials(&incoming.user_token).await {
+            Ok(c) => c,
+            Err(message) => {
+                send_error(&mut ws_stream, AuthenticationError { message }).await?;
+                return Ok(());
+            }
+        };
+
+    let checkpoint_store: Arc<dyn CheckpointStore> = Arc::new(MongoUtil::new().await?);
+
+    let (session_id, checkpoint) = match incoming.resume_session_id.clone() {
+        Some(session_id) => match checkpoint_store.load(&session_id).await {
+            Ok(Some(checkpoint))
+                if checkpoint.user_id == user_id
+                    && checkpoint.onshape_document_id == incoming.onshape_document_id =>
+            {
+                (session_id, Some(checkpoint))
+            }
+            Ok(_) => {
+                send_error(
+                    &mut ws_stream,
+                    RequestError {
+                        message: format!("No checkpoint exists for session {session_id}"),
+                        operation: "resume".to_owned(),
+                    },
+                )
+                .await?;
+                return Ok(());
+            }
+            Err(err) => {
+                println!("failed to load checkpoint: {}", err);
+                send_error(
+                    &mut ws_stream,
+                    InternalError {
+                        message: "Failed to load session checkpoint".to_owned(),
+                    },
+                )
+                .await?;
+                return Ok(());
+            }
+        },
+        None => (Uuid::new_v4().to_string(), None),
     };
 
-    _ = credentials; // TODO: remove once credentials are used
+    let resumed = checkpoint.is_some();
+    if resumed {
+        println!("resuming session with id {session_id}");
+    } else {
+        println!("staring session with id {session_id}");
+    }
+
+    // A resumed session keeps the options it was started with
+    let (resumed_state, resumed_config) = match checkpoint {
+        Some(checkpoint) => (Some(checkpoint.state), checkpoint.config),
+        None => (None, None),
+    };
+    let mut config = match resumed_config {
+        Some(config) => config,
+        None => config_from_request(&incoming),
+    };
+
+    let needs_part_studio = config.build_mode != BuildMode::Isolated
+        && config.part_studio.element_id.is_none()
+        && config.part_studio.part_studio_name.is_none();
+    if let Err(err) = check_document(
+        &credentials,
+        &incoming.onshape_document_id,
+        config.part_studio.workspace_id.as_deref(),
+        needs_part_studio,
+    )
+    .await
+    {
+        println!("document failed pre-flight checks: {}", err);
+        send_error(
+            &mut ws_stream,
+            DocumentError {
+                message: err.to_string(),
+                reason: err.reason().to_owned(),
+            },
+        )
+        .await?;
+        return Ok(());
+    }
+
+    match resolve_part_studio(
+        &credentials,
+        &incoming.onshape_document_id,
+        config.part_studio.clone(),
+        config.build_mode,
+        resumed,
+    )
+    .await
+    {
+        Ok(part_studio) => config.part_studio = part_studio,
+        Err(err) => {
+            println!("part studio selection is invalid: {}", err);
+            send_error(
+                &mut ws_stream,
+                RequestError {
+                    message: err.to_string(),
+                    operation: "select_part_studio".to_owned(),
+                },
+            )
+            .await?;
+            return Ok(());
+        }
+    }
 
-    let session_id = Uuid::new_v4().to_string();
-    println!("staring session with id {session_id}");
+    send_message(
+        &mut ws_stream,
+        SessionStartResponse {
+            session_id: session_id.clone(),
+            resumed,
+        },
+    )
+    .await?;
 
-    send_message(&mut ws_stream, SessionStartResponse { session_id }).await?;
+    let state = match resumed_state {
+        Some(state) => state,
+        None => {
+            let initial_input: UserPromptInitial = wait_for_message(&mut ws_stream).await?;
+            SessionState::new(&initial_input.contents)
+        }
+    };
 
-    let initial_input: UserPromptInitial = wait_for_message(&mut ws_stream).await?;
+    let checkpointer = Checkpointer::new(
+        checkpoint_store,
+        session_id,
+        user_id,
+        incoming.onshape_document_id.clone(),
+        config.clone(),
+    );
 
-    let stream_mutex = Mutex::new(ws_stream);
+    let transport = Arc::new(WebSocketTransport {
+        ws_stream: Mutex::new(ws_stream),
+    });
 
     if let Err(err) = enter_chain(
-        &initial_input.contents,
+        state,
         credentials,
         incoming.onshape_document_id,
-        |input: String| Box::pin(query_input_callback(&stream_mutex, input)),
-        |output: ServerResponse| Box::pin(send_output_callback(&stream_mutex, output)),
+        config,
+        SessionIo::new(transport.clone()),
+        checkpointer,
     )
     .await
     {
         println!("LLM Chain Crashed with error: {}", err);
-        let mut frame = stream_mutex.lock().await;
+        let mut frame = transport.ws_stream.lock().await;
         send_error(
             &mut frame,
             InternalError {
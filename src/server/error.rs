@@ -48,3 +48,14 @@ impl SocketError for InternalError {
         "InternalError".to_string()
     }
 }
+
+#[derive(Serialize)]
+pub struct DocumentError {
+    pub message: String,
+    pub reason: String,
+}
+impl SocketError for DocumentError {
+    fn name() -> String {
+        "DocumentError".to_string()
+    }
+}
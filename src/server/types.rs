@@ -1,39 +1,134 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-pub const ONSHAPE_API: &str = "https://cad.onshape.com/api/v6";
-pub const OPENAI_API: &str = "https://api.openai.com/v1";
+use crate::chain::export::ExportFormat;
+use crate::chain::onshape::PartStudioSelection;
+use crate::chain::pipeline::StageKind;
+use crate::chain::units::LengthUnit;
+
+/// The Onshape API used unless ONSHAPE_API_URL points somewhere else, such as
+/// a local mock server
+pub const DEFAULT_ONSHAPE_API: &str = "https://cad.onshape.com/api/v6";
+/// The OpenAI API used unless OPENAI_API_BASE_URL points somewhere else. The
+/// chain's OpenAI executor reads the same variable.
+pub const DEFAULT_OPENAI_API: &str = "https://api.openai.com/v1";
+
+/// The base URL of the Onshape API, without a trailing slash
+pub fn onshape_api() -> String {
+    match std::env::var("ONSHAPE_API_URL") {
+        Ok(url) if !url.trim().is_empty() => url.trim().trim_end_matches('/').to_owned(),
+        _ => DEFAULT_ONSHAPE_API.to_owned(),
+    }
+}
+
+/// The base URL of the OpenAI API, without a trailing slash
+pub fn openai_api() -> String {
+    match std::env::var("OPENAI_API_BASE_URL") {
+        Ok(url) if !url.trim().is_empty() => url.trim().trim_end_matches('/').to_owned(),
+        _ => DEFAULT_OPENAI_API.to_owned(),
+    }
+}
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SessionStartRequest {
     pub user_token: String,
     pub onshape_document_id: String,
+    #[serde(default)]
+    pub pipeline: Option<Vec<StageKind>>,
+    #[serde(default)]
+    pub resume_session_id: Option<String>,
+    #[serde(default)]
+    pub display_unit: Option<LengthUnit>,
+    #[serde(default)]
+    pub pessimist_max_turns: Option<usize>,
+    #[serde(default)]
+    pub approve_outline: bool,
+    /// Lets the session replace the features in the document's first part
+    /// studio. Otherwise it builds in a part studio of its own.
+    #[serde(default)]
+    pub allow_wipe: bool,
+    /// Treats the request as a change to the model already in the part studio
+    #[serde(default)]
+    pub edit_existing: bool,
+    #[serde(flatten)]
+    pub part_studio: PartStudioSelection,
+    /// Formats to export the built model to and send back; none by default
+    #[serde(default)]
+    pub export_formats: Option<Vec<ExportFormat>>,
 }
 
 #[derive(Serialize)]
 pub struct SessionStartResponse {
     pub session_id: String,
+    pub resumed: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct UserPromptInitial {
     pub contents: String,
 }
+/// How the user answered a request to review something
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewAction {
+    Approve,
+    /// `response` describes the changes to make
+    RequestChanges,
+    /// `response` is the user's edited version
+    Edit,
+    /// Go back to the previous version
+    Undo,
+    /// Go back to the version built in `iteration`
+    RevertTo {
+        iteration: usize,
+    },
+    /// Stop without making further changes
+    Stop,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserInputResponse {
     pub response: String,
+    #[serde(default)]
+    pub action: Option<ReviewAction>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, PartialEq)]
 pub enum ServerResponseType {
     Query,
     Info,
+    Stream,
+    Progress,
+    Spec,
+    Rejection,
+    Plan,
+    Approval,
+    Artifact,
     Final,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct ServerResponse {
     pub response_type: ServerResponseType,
     pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+}
+
+impl ServerResponse {
+    pub fn new(response_type: ServerResponseType, content: impl Into<String>) -> Self {
+        ServerResponse {
+            response_type,
+            content: content.into(),
+            payload: None,
+        }
+    }
+
+    /// Attaches structured data for the client alongside the text content
+    pub fn with_payload(mut self, payload: Value) -> Self {
+        self.payload = Some(payload);
+        self
+    }
 }
 
 #[derive(Debug)]